@@ -1,5 +1,6 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
+    fs,
     io::{Write, stdout},
 };
 
@@ -7,47 +8,112 @@ use once_cell::sync::Lazy;
 
 use crate::{
     errors::RuntimeError,
-    scope::{FUNCTIONS, VARIABLES, get_variable, set_function, set_variable},
-    token::{Expression, Identifier, List, Token},
-    utils::unescape,
+    host::Builtin,
+    modules,
+    parser::Parser,
+    scope::{Function, Scope},
+    source::{Span, location},
+    stdlib::{
+        list,
+        math::{self, DivisionByZero},
+        string,
+    },
+    token::{Expression, Identifier, List, Number, Object, Tail, Token, evaluate},
 };
 
-type BuiltinFunc = fn(List, Option<String>) -> Result<Token, RuntimeError>;
+/// Builtin function, it gets its arguments already evaluated
+pub type BuiltinFunc = fn(List, &Scope) -> Result<Token, RuntimeError>;
+/// Special form, it gets its arguments as written and decides itself what to
+/// evaluate. It may hand an expression in tail position back to the evaluator
+/// instead of evaluating it.
+type SpecialForm = fn(List, &Scope) -> Result<Tail, RuntimeError>;
 
-pub static BUILTIN_FUNCTIONS: Lazy<HashMap<String, BuiltinFunc>> = Lazy::new(|| {
+/// Syntax of the language. Unlike builtins these can't be redefined per
+/// interpreter.
+pub static SPECIAL_FORMS: Lazy<HashMap<String, SpecialForm>> = Lazy::new(|| {
     [
-        ("var", create_variable as BuiltinFunc),
+        // Definitions
+        ("var", create_variable as SpecialForm),
+        ("set!", assign),
         ("func", create_function),
+        ("lambda", create_lambda),
+        ("fn", create_lambda),
         ("typeof", typeof_),
+        // Macros
+        ("quote", quote),
+        ("quasiquote", quasiquote),
+        ("defmacro", create_macro),
+        // Modules
+        ("import", import),
+        ("export", export),
         // Control flow
         ("if", if_then_else),
-        // while
+        ("do", do_),
+        ("begin", do_),
+        ("call", call),
+        ("let", let_),
+        ("let*", let_star),
+        ("try", try_),
+        ("while", while_),
+        ("for", for_),
+        ("loop", loop_),
+        ("break", break_),
+        ("continue", continue_),
+        // Logic
+        ("and", and),
+        ("or", or),
+        ("cond", cond),
+        ("case", case),
+        ("switch", case),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v))
+    .collect()
+});
 
+/// The standard builtins every new global scope starts with.
+pub fn builtin_functions() -> HashMap<String, Builtin> {
+    [
         // Comparing
-        ("eq", equal),
+        ("eq", equal as BuiltinFunc),
         ("ne", not_equal),
         ("lt", less_then),
         ("gt", greater_then),
         ("le", less_or_equal),
         ("ge", greater_or_equal),
-        // // Math
-        ("add", add),
-        ("sub", sub),
-        ("mul", mul),
-        ("div", div),
+        ("not", not),
+        ("throw", throw),
+        ("macroexpand", macroexpand),
+        ("macroexpand-1", macroexpand_1),
+        ("gensym", gensym),
+        // Objects
+        ("get", object_get),
+        ("set", object_set),
+        ("keys", object_keys),
+        ("values", object_values),
+        ("has", object_has),
+        ("remove", object_remove),
+        ("merge", object_merge),
+        // Reader
+        ("read", read),
+        ("parse", parse),
+        ("eval", eval),
+        ("load", load),
         // // Other
         ("concat", concat),
         ("print", print),
+        ("repr", repr),
     ]
     .into_iter()
-    .map(|(k, v)| (k.to_string(), v))
+    .chain(math::FUNCTIONS.iter().copied())
+    .chain(math::division_functions(DivisionByZero::default()))
+    .chain(string::FUNCTIONS.iter().copied())
+    .chain(list::FUNCTIONS.iter().copied())
+    .map(|(k, v)| (k.to_string(), Builtin::native(v)))
     .collect()
-});
+}
 
-pub fn create_variable(
-    mut tokens: List,
-    maybe_prefix: Option<String>,
-) -> Result<Token, RuntimeError> {
+pub fn create_variable(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
     if tokens.0.len() != 2 {
         return Err(RuntimeError::InvalidArgCount {
             expected: 2,
@@ -55,33 +121,102 @@ pub fn create_variable(
         });
     }
 
-    let id: Identifier = tokens.0.pop_front().unwrap().extract(None)?;
+    let id: Identifier = tokens.0.pop_front().unwrap().extract(scope)?;
 
-    let value = tokens.0.pop_front().unwrap().into_value(maybe_prefix)?;
+    let value = tokens.0.pop_front().unwrap().into_value(scope)?;
 
-    set_variable(&id.0, value);
+    scope.set_variable(&id, value.clone());
 
-    Ok(get_variable(&id.0))
+    Ok(Tail::Value(value))
 }
 
-pub fn create_function(mut tokens: List, _: Option<String>) -> Result<Token, RuntimeError> {
-    if tokens.0.len() != 3 {
+/// `(set! name value)` changes the value of a name bound by this or by an
+/// enclosing frame, where `var` would bind a new name in this frame.
+pub fn assign(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    if tokens.0.len() != 2 {
         return Err(RuntimeError::InvalidArgCount {
-            expected: 3,
+            expected: 2,
             got: tokens.0.len(),
         });
     }
 
-    let id: Identifier = tokens.0.pop_front().unwrap().extract(None)?;
-    let arg_names: List = tokens.0.pop_front().unwrap().extract(None)?;
-    let body: Expression = tokens.0.pop_front().unwrap().extract(None)?;
+    let id: Identifier = tokens.0.pop_front().unwrap().extract(scope)?;
+
+    let value = tokens.0.pop_front().unwrap().into_value(scope)?;
+
+    scope.assign(&id, value.clone())?;
+
+    Ok(Tail::Value(value))
+}
+
+/// A function body is a single expression or several forms run in order.
+fn function_body(mut forms: List) -> Expression {
+    if let (1, Some(Token::Expression(_))) = (forms.len(), forms.front()) {
+        let Some(Token::Expression(e)) = forms.pop_front() else {
+            unreachable!()
+        };
+
+        return e;
+    }
+
+    let span = forms
+        .iter()
+        .find_map(|form| match form {
+            Token::Expression(e) => Some(e.span.clone()),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    Expression {
+        fid: Some(Identifier("do".to_string())),
+        args: forms,
+        span,
+        spans: Vec::new(),
+    }
+}
+
+pub fn create_function(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    if tokens.0.len() < 3 {
+        return Err(RuntimeError::NotEnoughArgs { min: 3 });
+    }
+
+    let id: Identifier = tokens.0.pop_front().unwrap().extract(scope)?;
+    let arg_names: List = tokens.0.pop_front().unwrap().extract(scope)?;
+    let body = function_body(tokens);
+
+    scope.set_function(&id, arg_names, body)?;
+
+    Ok(Tail::Value(Token::Nil))
+}
+
+pub fn create_lambda(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    if tokens.0.len() < 2 {
+        return Err(RuntimeError::NotEnoughArgs { min: 2 });
+    }
+
+    let arg_names: List = tokens.0.pop_front().unwrap().extract(scope)?;
+    let body = function_body(tokens);
+
+    let function = Function::new(body, arg_names, scope.clone())?;
+
+    Ok(Tail::Value(Token::Function(function)))
+}
+
+pub fn call(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    if tokens.is_empty() {
+        return Err(RuntimeError::NotEnoughArgs { min: 1 });
+    }
 
-    set_function(&id.0, arg_names, body)?;
+    let function: Function = tokens
+        .pop_front()
+        .unwrap()
+        .into_value(scope)?
+        .extract(scope)?;
 
-    return Ok(Token::Nil);
+    Ok(Tail::Call(function, evaluate(&tokens, scope)?))
 }
 
-pub fn typeof_(mut tokens: List, maybe_prefix: Option<String>) -> Result<Token, RuntimeError> {
+pub fn typeof_(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
     if tokens.len() != 1 {
         return Err(RuntimeError::InvalidArgCount {
             expected: 1,
@@ -91,24 +226,220 @@ pub fn typeof_(mut tokens: List, maybe_prefix: Option<String>) -> Result<Token,
 
     let type_ = match tokens.pop_front().unwrap() {
         Token::Identifier(name) => {
-            if VARIABLES.read().unwrap().contains_key(&name.0) {
-                get_variable(&name).as_type()
-            } else if FUNCTIONS.read().unwrap().contains_key(&name.0)
-                || BUILTIN_FUNCTIONS.contains_key(&name.0)
+            if scope.has_variable(&name) {
+                scope.get_variable(&name).as_type()
+            } else if scope.get_macro(&name).is_some() {
+                "macro".to_string()
+            } else if scope.get_function(&name).is_some()
+                || scope.get_builtin(&name).is_some()
+                || SPECIAL_FORMS.contains_key(&name.0)
             {
                 "function".to_string()
             } else {
                 return Err(RuntimeError::UndefinedFunction(name.0));
             }
         }
-        t @ Token::Expression(_) => t.into_value(maybe_prefix)?.as_type(),
+        t @ Token::Expression(_) => t.into_value(scope)?.as_type(),
         t => t.as_type(),
     };
 
-    Ok(Token::String(type_))
+    Ok(Tail::Value(Token::String(type_)))
+}
+
+pub fn quote(tokens: List, _: &Scope) -> Result<Tail, RuntimeError> {
+    match <[Token; 1]>::try_from(Vec::from(tokens.0)) {
+        Ok([token]) => Ok(Tail::Value(token)),
+        Err(tokens) => Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        }),
+    }
+}
+
+pub fn quasiquote(tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    let Tail::Value(template) = quote(tokens, scope)? else {
+        unreachable!()
+    };
+
+    Ok(Tail::Value(unquote(template, scope)?))
+}
+
+fn is_form(e: &Expression, name: &str) -> bool {
+    e.fid.as_ref().is_some_and(|fid| fid.as_str() == name)
+}
+
+/// The single argument of `(unquote x)` or `(unquote-splicing x)`, evaluated.
+fn unquoted(e: Expression, scope: &Scope) -> Result<Token, RuntimeError> {
+    let Tail::Value(token) = quote(e.args, scope)? else {
+        unreachable!()
+    };
+
+    token.into_value(scope)
+}
+
+/// Copies `template` with every `(unquote x)` inside it replaced by the
+/// value of `x`.
+fn unquote(template: Token, scope: &Scope) -> Result<Token, RuntimeError> {
+    match template {
+        Token::Expression(e) if is_form(&e, "unquote") => unquoted(e, scope),
+        Token::Expression(e) if is_form(&e, "unquote-splicing") => Err(RuntimeError::InvalidValue(
+            "`unquote-splicing` outside of a list".to_string(),
+        )),
+        Token::Expression(e) => {
+            // `(,f x)` is read as `(call (unquote f) x)`, the head gets
+            // back its place once it's known
+            let unquoted_head = is_form(&e, "call")
+                && matches!(e.args.front(), Some(Token::Expression(head))
+                    if is_form(head, "unquote") || is_form(head, "unquote-splicing"));
+
+            let span = e.span.clone();
+            let mut items = splice(form_items(e), scope)?;
+
+            if unquoted_head {
+                items.pop_front();
+            }
+
+            Ok(Token::Expression(Expression::from_iterable(items, span)))
+        }
+        Token::List(list) => Ok(Token::List(List::from_iterable(splice(list.0, scope)?))),
+        token => Ok(token),
+    }
+}
+
+/// Unquotes `items`, inlining the items of `(unquote-splicing xs)`.
+fn splice<T>(items: T, scope: &Scope) -> Result<VecDeque<Token>, RuntimeError>
+where
+    T: IntoIterator<Item = Token>,
+{
+    let mut spliced = VecDeque::new();
+
+    for item in items {
+        match item {
+            Token::Expression(e) if is_form(&e, "unquote-splicing") => match unquoted(e, scope)? {
+                Token::List(list) => spliced.extend(list.0),
+                token => {
+                    return Err(RuntimeError::TypeMismatch {
+                        expected: "list".to_string(),
+                        found: token.as_type(),
+                    });
+                }
+            },
+            item => spliced.push_back(unquote(item, scope)?),
+        }
+    }
+
+    Ok(spliced)
+}
+
+/// The head and the arguments of an expression as one sequence.
+fn form_items(e: Expression) -> impl Iterator<Item = Token> {
+    e.fid.map(Token::Identifier).into_iter().chain(e.args.0)
+}
+
+pub fn create_macro(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    if tokens.0.len() < 3 {
+        return Err(RuntimeError::NotEnoughArgs { min: 3 });
+    }
+
+    let id: Identifier = tokens.0.pop_front().unwrap().extract(scope)?;
+    let arg_names: List = tokens.0.pop_front().unwrap().extract(scope)?;
+    let body = function_body(tokens);
+
+    scope.set_macro(&id, arg_names, body)?;
+
+    Ok(Tail::Value(Token::Nil))
+}
+
+/// Expands `token` once if it's a call of a macro visible from `scope`.
+pub fn expand_macro(token: Token, scope: &Scope) -> Result<Option<Token>, RuntimeError> {
+    let Token::Expression(e) = token else {
+        return Ok(None);
+    };
+
+    let Some(fid) = &e.fid else {
+        return Ok(None);
+    };
+
+    if SPECIAL_FORMS.contains_key(fid.as_str()) {
+        return Ok(None);
+    }
+
+    match scope.get_macro(fid) {
+        Some(m) => m.apply(e.args.0.into()).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// `(import "path")` binds the exports of a module as `name/export`, `name`
+/// being the file name or the alias given with `(import "path" as alias)`.
+/// `(import "path" [a b])` binds only the listed exports under their own
+/// names. Either way the exports are returned as an object.
+pub fn import(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    if tokens.is_empty() {
+        return Err(RuntimeError::NotEnoughArgs { min: 1 });
+    }
+
+    let path = match tokens.pop_front().unwrap().into_value(scope)? {
+        Token::String(path) => path,
+        token => {
+            return Err(RuntimeError::TypeMismatch {
+                expected: "string".to_string(),
+                found: token.as_type(),
+            });
+        }
+    };
+
+    let exports = modules::import(&path, scope)?;
+
+    let prefix = match Vec::from(tokens.0).as_slice() {
+        [] => Some(modules::default_prefix(&path)),
+        [Token::Identifier(as_), Token::Identifier(alias)] if as_.as_str() == "as" => {
+            Some(alias.to_string())
+        }
+        [Token::List(names)] => {
+            for name in names.iter() {
+                let name: Identifier = name.clone().extract(scope)?;
+
+                let Some(value) = exports.get(name.as_str()) else {
+                    return Err(RuntimeError::InvalidValue(format!(
+                        "module `{}` doesn't export `{}`",
+                        path, name.0
+                    )));
+                };
+
+                scope.set_variable(&name, value.clone());
+            }
+
+            None
+        }
+        _ => {
+            return Err(RuntimeError::InvalidValue(
+                "expected `as <alias>` or a list of names after the module path".to_string(),
+            ));
+        }
+    };
+
+    if let Some(prefix) = prefix {
+        for (name, value) in exports.iter() {
+            scope.set_variable(&format!("{}/{}", prefix, name), value.clone());
+        }
+    }
+
+    Ok(Tail::Value(Token::Object(exports)))
 }
 
-pub fn if_then_else(mut tokens: List, maybe_prefix: Option<String>) -> Result<Token, RuntimeError> {
+/// `(export a b)` limits what importers of the module see to the listed
+/// names.
+pub fn export(tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    for token in tokens.0 {
+        let name: Identifier = token.extract(scope)?;
+        scope.export(&name);
+    }
+
+    Ok(Tail::Value(Token::Nil))
+}
+
+pub fn if_then_else(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
     if tokens.len() < 2 {
         return Err(RuntimeError::NotEnoughArgs { min: 2 });
     }
@@ -118,25 +449,435 @@ pub fn if_then_else(mut tokens: List, maybe_prefix: Option<String>) -> Result<To
     }
 
     let has_else_block = tokens.len() == 3;
-    let condition: bool = tokens.pop_front().unwrap().extract(maybe_prefix.clone())?;
+    let condition: bool = tokens
+        .pop_front()
+        .unwrap()
+        .into_value(scope)?
+        .extract(scope)?;
 
     let then_block = tokens.pop_front().unwrap();
 
-    Ok(match condition {
-        true => then_block.into_value(maybe_prefix)?,
-        false => {
-            let else_block = match has_else_block {
-                true => tokens.pop_front().unwrap(),
-                false => Token::Nil,
-            };
+    let block = match condition {
+        true => then_block,
+        false => match has_else_block {
+            true => tokens.pop_front().unwrap(),
+            false => Token::Nil,
+        },
+    };
+
+    Ok(Tail::Eval(block, scope.clone()))
+}
+
+/// Evaluates the forms in order, the last one is in tail position.
+pub fn do_(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    let Some(last) = tokens.pop_back() else {
+        return Ok(Tail::Value(Token::Nil));
+    };
+
+    for token in tokens.0 {
+        token.into_value(scope)?;
+    }
+
+    Ok(Tail::Eval(last, scope.clone()))
+}
+
+/// `(let [name value ...] body...)` evaluates all values first, then runs the
+/// body in a new frame holding the bindings.
+pub fn let_(tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    bind(tokens, scope, false)
+}
+
+/// `(let* [name value ...] body...)` binds the names one by one, so a value
+/// can refer to the names bound before it.
+pub fn let_star(tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    bind(tokens, scope, true)
+}
 
-            else_block.into_value(maybe_prefix)?
+fn bind(mut tokens: List, scope: &Scope, sequential: bool) -> Result<Tail, RuntimeError> {
+    if tokens.is_empty() {
+        return Err(RuntimeError::NotEnoughArgs { min: 1 });
+    }
+
+    let bindings: List = tokens.pop_front().unwrap().extract(scope)?;
+
+    if !bindings.len().is_multiple_of(2) {
+        return Err(RuntimeError::InvalidValue(
+            "let bindings must be name value pairs".to_string(),
+        ));
+    }
+
+    let frame = scope.child();
+    let mut bindings = bindings.into_spanned();
+
+    while let (Some((name, span)), Some((value, _))) = (bindings.next(), bindings.next()) {
+        let name: Identifier = name.extract(scope).map_err(|e| e.located_at(span))?;
+        let value = value.into_value(if sequential { &frame } else { scope })?;
+
+        frame.set_variable(&name, value);
+    }
+
+    do_(tokens, &frame)
+}
+
+/// Evaluates the forms in order and returns the value of the last one.
+fn run_forms<T>(forms: T, scope: &Scope) -> Result<Token, RuntimeError>
+where
+    T: IntoIterator<Item = Token>,
+{
+    let mut value = Token::Nil;
+
+    for form in forms {
+        value = form.into_value(scope)?;
+    }
+
+    Ok(value)
+}
+
+/// `(try body... (catch e handler...) (finally cleanup...))` runs the body and
+/// hands an error to the first matching `catch`. A catch clause may list the
+/// kinds it handles: `(catch "type-mismatch" e ...)` or `(catch [...] e ...)`.
+/// The cleanup runs however the body or the handler finishes.
+pub fn try_(tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    let mut body = Vec::new();
+    let mut handlers = Vec::new();
+    let mut cleanup = None;
+
+    for token in tokens.0 {
+        match &token {
+            Token::Expression(e) if e.fid.as_ref().is_some_and(|id| id.0 == "catch") => {
+                handlers.push(e.args.clone())
+            }
+            Token::Expression(e) if e.fid.as_ref().is_some_and(|id| id.0 == "finally") => {
+                cleanup = Some(e.args.clone())
+            }
+            _ => body.push(token),
         }
-    })
+    }
+
+    let result = match run_forms(body, scope) {
+        Err(RuntimeError::Break(value)) => Err(RuntimeError::Break(value)),
+        Err(RuntimeError::Continue(value)) => Err(RuntimeError::Continue(value)),
+        Err(error) => catch(error, handlers, scope),
+        result => result,
+    };
+
+    if let Some(cleanup) = cleanup {
+        run_forms(cleanup.0, scope)?;
+    }
+
+    result.map(Tail::Value)
+}
+
+fn catch(error: RuntimeError, handlers: Vec<List>, scope: &Scope) -> Result<Token, RuntimeError> {
+    let kind = error.kind_name();
+
+    for mut handler in handlers {
+        let kinds = match handler.front() {
+            Some(Token::String(_) | Token::List(_)) => handler.pop_front(),
+            _ => None,
+        };
+
+        let matches = match kinds {
+            None => true,
+            Some(Token::String(k)) => k == kind,
+            Some(Token::List(kinds)) => kinds.contains(&Token::String(kind.clone())),
+            Some(_) => unreachable!(),
+        };
+
+        if !matches {
+            continue;
+        }
+
+        let Some(name) = handler.pop_front() else {
+            return Err(RuntimeError::NotEnoughArgs { min: 1 });
+        };
+
+        let name: Identifier = name.extract(scope)?;
+        let frame = scope.child();
+        frame.set_variable(&name, error_value(&error));
+
+        return run_forms(handler.0, &frame);
+    }
+
+    Err(error)
+}
+
+/// The caught error as scripts see it: `{kind message value location}`.
+fn error_value(error: &RuntimeError) -> Token {
+    let value = match error.kind() {
+        RuntimeError::Thrown(value) => (**value).clone(),
+        _ => Token::Nil,
+    };
+
+    let location = error
+        .span()
+        .and_then(location)
+        .map_or(Token::Nil, Token::String);
+
+    let mut object = Object::default();
+    object.insert("kind".to_string(), Token::String(error.kind_name()));
+    object.insert("message".to_string(), Token::String(error.message()));
+    object.insert("value".to_string(), value);
+    object.insert("location".to_string(), location);
+
+    Token::Object(object)
+}
+
+/// `(and a b ...)` returns the first falsy value without evaluating the rest,
+/// or the last value.
+pub fn and(tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    short_circuit(tokens, scope, false)
+}
+
+/// `(or a b ...)` returns the first truthy value without evaluating the rest,
+/// or the last value.
+pub fn or(tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    short_circuit(tokens, scope, true)
+}
+
+fn short_circuit(mut tokens: List, scope: &Scope, stop_on: bool) -> Result<Tail, RuntimeError> {
+    let Some(last) = tokens.pop_back() else {
+        return Ok(Tail::Value(Token::Bool(!stop_on)));
+    };
+
+    for token in tokens.0 {
+        let value = token.into_value(scope)?;
+
+        if value.clone().extract::<bool>(scope)? == stop_on {
+            return Ok(Tail::Value(value));
+        }
+    }
+
+    Ok(Tail::Eval(last, scope.clone()))
+}
+
+/// Marks the clause of `cond` and `case` that always matches.
+pub fn is_else(token: &Token) -> bool {
+    matches!(token, Token::Identifier(id) if id.0 == "else")
+}
+
+/// Splits a `[test body...]` clause of `cond` or `case`.
+fn clause(token: Token, scope: &Scope) -> Result<(Token, List), RuntimeError> {
+    let mut clause: List = token.extract(scope)?;
+
+    match clause.pop_front() {
+        Some(test) => Ok((test, clause)),
+        None => Err(RuntimeError::InvalidValue("empty clause".to_string())),
+    }
+}
+
+/// `(cond [test body...] ... [else body...])` runs the body of the first
+/// clause whose test is truthy.
+pub fn cond(tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    for token in tokens.0 {
+        let (test, body) = clause(token, scope)?;
+
+        if is_else(&test) || test.into_value(scope)?.extract::<bool>(scope)? {
+            return do_(body, scope);
+        }
+    }
+
+    Ok(Tail::Value(Token::Nil))
+}
+
+/// `(case value [key body...] ... [else body...])` runs the body of the first
+/// clause whose key equals the value.
+pub fn case(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    if tokens.is_empty() {
+        return Err(RuntimeError::NotEnoughArgs { min: 1 });
+    }
+
+    let value = tokens.pop_front().unwrap().into_value(scope)?;
+
+    for token in tokens.0 {
+        let (key, body) = clause(token, scope)?;
+
+        if is_else(&key) || key.into_value(scope)? == value {
+            return do_(body, scope);
+        }
+    }
+
+    Ok(Tail::Value(Token::Nil))
+}
+
+/// Runs one iteration of a loop body. Returns the value passed to `break`
+/// when the loop has to stop, the value passed to `continue` is kept in
+/// `result`.
+fn run_loop_body(
+    body: &List,
+    scope: &Scope,
+    result: &mut Token,
+) -> Result<Option<Token>, RuntimeError> {
+    for token in body.iter() {
+        match token.clone().into_value(scope) {
+            Ok(_) => (),
+            Err(RuntimeError::Continue(value)) => {
+                *result = *value;
+                return Ok(None);
+            }
+            Err(RuntimeError::Break(value)) => return Ok(Some(*value)),
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(None)
+}
+
+/// `(while condition ...)` runs the body as long as the condition holds. Like
+/// with `for`, each iteration runs in a frame of its own, so enclosing
+/// bindings are updated with `set!`.
+pub fn while_(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    if tokens.is_empty() {
+        return Err(RuntimeError::NotEnoughArgs { min: 1 });
+    }
+
+    let condition = tokens.pop_front().unwrap();
+    let mut result = Token::Nil;
+
+    while condition
+        .clone()
+        .into_value(scope)?
+        .extract::<bool>(scope)?
+    {
+        if let Some(value) = run_loop_body(&tokens, &scope.child(), &mut result)? {
+            return Ok(Tail::Value(value));
+        }
+    }
+
+    Ok(Tail::Value(result))
+}
+
+/// `(for [x list] ...)` walks a list or the chars of a string,
+/// `(for [i start end step?] ...)` counts from `start` up to `end` exclusive.
+/// Each iteration runs in a frame of its own where the loop variable is
+/// bound, so enclosing bindings are updated with `set!`.
+pub fn for_(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    if tokens.is_empty() {
+        return Err(RuntimeError::NotEnoughArgs { min: 1 });
+    }
+
+    let binding: List = tokens.pop_front().unwrap().extract(scope)?;
+
+    if binding.len() < 2 {
+        return Err(RuntimeError::NotEnoughArgs { min: 2 });
+    }
+
+    if binding.len() > 4 {
+        return Err(RuntimeError::TooMuchArgs { max: 4 });
+    }
+
+    let mut result = Token::Nil;
+    let ranged = binding.len() > 2;
+    let mut binding = binding.into_spanned();
+
+    let (id, span) = binding.next().unwrap();
+    let id: Identifier = id.extract(scope).map_err(|e| e.located_at(span))?;
+
+    if !ranged {
+        let (items, span) = binding.next().unwrap();
+
+        let items: Vec<Token> = match items.into_value(scope)? {
+            Token::List(list) => list.0.into(),
+            Token::String(string) => string.chars().map(|c| Token::String(c.into())).collect(),
+            token => {
+                return Err(RuntimeError::TypeMismatch {
+                    expected: "list".to_string(),
+                    found: token.as_type(),
+                }
+                .located_at(span));
+            }
+        };
+
+        for item in items {
+            let frame = scope.child();
+            frame.set_variable(&id, item);
+
+            if let Some(value) = run_loop_body(&tokens, &frame, &mut result)? {
+                return Ok(Tail::Value(value));
+            }
+        }
+
+        return Ok(Tail::Value(result));
+    }
+
+    let bound = |(token, span): (Token, Option<Span>)| -> Result<Number, RuntimeError> {
+        token
+            .into_value(scope)
+            .and_then(|value| value.extract(scope))
+            .map_err(|e| e.located_at(span))
+    };
+
+    let start = bound(binding.next().unwrap())?;
+    let end = bound(binding.next().unwrap())?;
+    let step = match binding.next() {
+        Some(item) => bound(item)?,
+        None => Number::Int(1),
+    };
+
+    if step.is_zero() {
+        return Err(RuntimeError::InvalidValue(
+            "for step can't be zero".to_string(),
+        ));
+    }
+
+    let mut i = start;
+
+    while i.in_range(&end, &step) {
+        let frame = scope.child();
+        frame.set_variable(&id, Token::Number(i.clone()));
+
+        if let Some(value) = run_loop_body(&tokens, &frame, &mut result)? {
+            return Ok(Tail::Value(value));
+        }
+
+        i = i.add(&step);
+    }
+
+    Ok(Tail::Value(result))
+}
+
+/// `(loop ...)` runs the body until a `break`, each iteration in a frame of
+/// its own.
+pub fn loop_(tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    let mut result = Token::Nil;
+
+    loop {
+        if let Some(value) = run_loop_body(&tokens, &scope.child(), &mut result)? {
+            return Ok(Tail::Value(value));
+        }
+    }
+}
+
+pub fn break_(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    if tokens.len() > 1 {
+        return Err(RuntimeError::TooMuchArgs { max: 1 });
+    }
+
+    let value = match tokens.pop_front() {
+        Some(token) => token.into_value(scope)?,
+        None => Token::Nil,
+    };
+
+    Err(RuntimeError::Break(Box::new(value)))
+}
+
+/// `(continue value?)` moves on to the next iteration. A loop that runs out
+/// of iterations returns the value of the last `continue`, or nil.
+pub fn continue_(mut tokens: List, scope: &Scope) -> Result<Tail, RuntimeError> {
+    if tokens.len() > 1 {
+        return Err(RuntimeError::TooMuchArgs { max: 1 });
+    }
+
+    let value = match tokens.pop_front() {
+        Some(token) => token.into_value(scope)?,
+        None => Token::Nil,
+    };
+
+    Err(RuntimeError::Continue(Box::new(value)))
 }
 
 // General
-pub fn compare(mut tokens: List, op: &str) -> Result<Token, RuntimeError> {
+pub fn compare(tokens: List, op: &str) -> Result<Token, RuntimeError> {
     if tokens.len() < 2 {
         return Err(RuntimeError::NotEnoughArgs { min: 2 });
     }
@@ -151,9 +892,10 @@ pub fn compare(mut tokens: List, op: &str) -> Result<Token, RuntimeError> {
         _ => unreachable!(),
     };
 
-    let base = tokens.pop_front().unwrap();
+    let mut tokens = tokens.0.into_iter();
+    let base = tokens.next().unwrap();
 
-    for t in tokens.0 {
+    for t in tokens {
         if !action(&base, &t) {
             return Ok(Token::Bool(false));
         }
@@ -162,110 +904,653 @@ pub fn compare(mut tokens: List, op: &str) -> Result<Token, RuntimeError> {
     Ok(Token::Bool(true))
 }
 
-pub fn equal(tokens: List, _: Option<String>) -> Result<Token, RuntimeError> {
+pub fn equal(tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
     compare(tokens, "==")
 }
 
-pub fn not_equal(tokens: List, _: Option<String>) -> Result<Token, RuntimeError> {
+pub fn not_equal(tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
     compare(tokens, "!=")
 }
 
-pub fn less_then(tokens: List, _: Option<String>) -> Result<Token, RuntimeError> {
+pub fn less_then(tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
     compare(tokens, "<")
 }
 
-pub fn greater_then(tokens: List, _: Option<String>) -> Result<Token, RuntimeError> {
+pub fn greater_then(tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
     compare(tokens, ">")
 }
 
-pub fn less_or_equal(tokens: List, _: Option<String>) -> Result<Token, RuntimeError> {
+pub fn less_or_equal(tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
     compare(tokens, "<=")
 }
 
-pub fn greater_or_equal(tokens: List, _: Option<String>) -> Result<Token, RuntimeError> {
-    compare(tokens, "=>")
+pub fn greater_or_equal(tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
+    compare(tokens, ">=")
 }
 
-pub fn add(tokens: List, maybe_prefix: Option<String>) -> Result<Token, RuntimeError> {
-    tokens
-        .0
-        .into_iter()
-        .try_fold(0.0, |acc, token| {
-            let value: f64 = token.extract(maybe_prefix.clone())?;
-            Ok(acc + value)
-        })
-        .map(Token::Number)
+pub fn not(mut tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 1 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        });
+    }
+
+    let value: bool = tokens.pop_front().unwrap().extract(scope)?;
+
+    Ok(Token::Bool(!value))
 }
 
-pub fn sub(tokens: List, maybe_prefix: Option<String>) -> Result<Token, RuntimeError> {
-    let mut tokens = tokens.0.into_iter();
+/// Expands the form once if its head is a macro, otherwise returns it as it
+/// is.
+pub fn macroexpand_1(mut tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 1 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        });
+    }
+
+    let form = tokens.pop_front().unwrap();
+
+    Ok(expand_macro(form.clone(), scope)?.unwrap_or(form))
+}
+
+/// Expands the form until its head is no longer a macro.
+pub fn macroexpand(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut form = macroexpand_1(tokens, scope)?;
+
+    while let Some(expanded) = expand_macro(form.clone(), scope)? {
+        form = expanded;
+    }
 
+    Ok(form)
+}
+
+/// Makes a fresh identifier for macros to bind without capturing the names
+/// used at the call site.
+pub fn gensym(mut tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() > 1 {
+        return Err(RuntimeError::TooMuchArgs { max: 1 });
+    }
+
+    let prefix = match tokens.pop_front() {
+        Some(token) => token.to_string(),
+        None => "g".to_string(),
+    };
+
+    let n = scope.next_symbol();
+
+    Ok(Token::Identifier(Identifier(format!("{}__{}", prefix, n))))
+}
+
+/// `(throw value)` raises an error carrying any value. An object can name
+/// its kind and message with `kind` and `message` keys.
+pub fn throw(mut tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 1 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        });
+    }
+
+    Err(RuntimeError::Thrown(Box::new(tokens.pop_front().unwrap())))
+}
+
+pub fn object_get(mut tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
     if tokens.len() < 2 {
         return Err(RuntimeError::NotEnoughArgs { min: 2 });
     }
 
-    let base: f64 = tokens.next().unwrap().extract(maybe_prefix.clone())?;
+    if tokens.len() > 3 {
+        return Err(RuntimeError::TooMuchArgs { max: 3 });
+    }
 
-    tokens
-        .try_fold(base, |acc, token| {
-            let value: f64 = token.extract(maybe_prefix.clone())?;
-            Ok(acc - value)
-        })
-        .map(Token::Number)
+    let object: Object = tokens.pop_front().unwrap().extract(scope)?;
+    let key: String = tokens.pop_front().unwrap().extract(scope)?;
+
+    match object.0.get(&key) {
+        Some(value) => Ok(value.clone()),
+        None => match tokens.pop_front() {
+            Some(default) => Ok(default),
+            None => Ok(Token::Nil),
+        },
+    }
 }
 
-pub fn mul(tokens: List, maybe_prefix: Option<String>) -> Result<Token, RuntimeError> {
-    tokens
-        .0
-        .into_iter()
-        .try_fold(1.0, |acc, token| {
-            let value: f64 = token.extract(maybe_prefix.clone())?;
-            Ok(acc * value)
-        })
-        .map(Token::Number)
+/// Objects are values, so `set`, `remove` and `merge` return an updated copy
+/// and leave their arguments untouched.
+pub fn object_set(mut tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 3 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 3,
+            got: tokens.len(),
+        });
+    }
+
+    let mut object: Object = tokens.pop_front().unwrap().extract(scope)?;
+    let key: String = tokens.pop_front().unwrap().extract(scope)?;
+    let value = tokens.pop_front().unwrap();
+
+    object.insert(key, value);
+
+    Ok(Token::Object(object))
 }
 
-pub fn div(tokens: List, maybe_prefix: Option<String>) -> Result<Token, RuntimeError> {
-    let mut tokens = tokens.0.into_iter();
+pub fn object_keys(mut tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 1 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        });
+    }
+
+    let object: Object = tokens.pop_front().unwrap().extract(scope)?;
 
+    Ok(Token::List(List::from_iterable(
+        object.0.into_keys().map(Token::String),
+    )))
+}
+
+pub fn object_values(mut tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 1 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        });
+    }
+
+    let object: Object = tokens.pop_front().unwrap().extract(scope)?;
+
+    Ok(Token::List(List::from_iterable(object.0.into_values())))
+}
+
+pub fn object_has(mut tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 2 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 2,
+            got: tokens.len(),
+        });
+    }
+
+    let object: Object = tokens.pop_front().unwrap().extract(scope)?;
+    let key: String = tokens.pop_front().unwrap().extract(scope)?;
+
+    Ok(Token::Bool(object.contains_key(&key)))
+}
+
+pub fn object_remove(mut tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
     if tokens.len() < 2 {
         return Err(RuntimeError::NotEnoughArgs { min: 2 });
     }
 
-    let base: f64 = tokens.next().unwrap().extract(maybe_prefix.clone())?;
+    let mut object: Object = tokens.pop_front().unwrap().extract(scope)?;
+
+    for token in tokens.0 {
+        let key: String = token.extract(scope)?;
+        object.remove(&key);
+    }
 
+    Ok(Token::Object(object))
+}
+
+pub fn object_merge(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
     tokens
-        .try_fold(base, |acc, token| {
-            let value: f64 = token.extract(maybe_prefix.clone())?;
-            Ok(acc / value)
+        .0
+        .into_iter()
+        .try_fold(Object::default(), |mut acc, token| {
+            let object: Object = token.extract(scope)?;
+            acc.extend(object.0);
+            Ok(acc)
         })
-        .map(Token::Number)
+        .map(Token::Object)
+}
+
+fn parse_source(mut tokens: List) -> Result<Vec<Token>, RuntimeError> {
+    if tokens.len() != 1 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        });
+    }
+
+    let source: String = match tokens.pop_front().unwrap() {
+        Token::String(source) => source,
+        token => {
+            return Err(RuntimeError::TypeMismatch {
+                expected: "string".to_string(),
+                found: token.as_type(),
+            });
+        }
+    };
+
+    Parser::with_name("<read>", &source)
+        .parse_forms()
+        .map_err(RuntimeError::Parse)
+}
+
+/// `(read "(add 1 2)")` returns the first form of the string as data, nil
+/// for an empty string.
+pub fn read(tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
+    Ok(parse_source(tokens)?
+        .into_iter()
+        .next()
+        .unwrap_or(Token::Nil))
+}
+
+/// Like `read`, but returns a list of every form in the string.
+pub fn parse(tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
+    Ok(Token::List(List::from_iterable(parse_source(tokens)?)))
+}
+
+/// Evaluates data as code in the environment of the caller.
+pub fn eval(mut tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 1 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        });
+    }
+
+    tokens.pop_front().unwrap().into_value(scope)
+}
+
+/// Runs every expression of a file in the environment of the caller and
+/// returns the value of the last one. Like imports, the path is looked up
+/// next to the loading file first and a file can't load itself again.
+pub fn load(mut tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 1 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        });
+    }
+
+    let path: String = tokens.pop_front().unwrap().extract(scope)?;
+    let file = modules::locate(&path, scope)?;
+    let source =
+        fs::read_to_string(&file).map_err(|e| RuntimeError::Io(format!("{}: {}", path, e)))?;
+
+    modules::run_file(scope, &file, || {
+        let expressions = Parser::with_name(&path, &source)
+            .parse_expressions()
+            .map_err(RuntimeError::Parse)?;
+
+        run_forms(expressions.into_iter().map(Token::Expression), scope)
+    })
 }
 
-pub fn concat(tokens: List, maybe_prefix: Option<String>) -> Result<Token, RuntimeError> {
+pub fn concat(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
     tokens
         .0
         .into_iter()
         .try_fold(String::new(), |acc, token| {
-            let value: String = token.extract(maybe_prefix.clone())?;
+            let value: String = token.extract(scope)?;
             Ok(acc + &value)
         })
         .map(Token::String)
 }
 
-pub fn print(tokens: List, maybe_prefix: Option<String>) -> Result<Token, RuntimeError> {
-    let mut parts = Vec::new();
-
-    for token in tokens.0 {
-        let value = token.into_value(maybe_prefix.clone())?;
-
-        parts.push(value.to_string());
+/// `(repr x)` is `x` written the way `read` reads it back, strings quoted.
+pub fn repr(tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 1 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        });
     }
 
+    Ok(Token::String(tokens[0].repr()))
+}
+
+pub fn print(tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
+    let parts: Vec<String> = tokens.iter().map(Token::to_string).collect();
     let output = parts.join(" ");
 
-    print!("{}", unescape(&output));
+    print!("{}", output);
     stdout().flush().unwrap();
 
     Ok(Token::Nil)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::stdlib::eval;
+
+    #[test]
+    fn cond_and_case() {
+        let sign = "(func sign [n] (cond [(lt n 0) \"negative\"] [(eq n 0) \"zero\"] [else \"positive\"]))";
+
+        assert_eq!(eval(&format!("{} (sign -1)", sign)), "negative");
+        assert_eq!(eval(&format!("{} (sign 0)", sign)), "zero");
+        assert_eq!(eval(&format!("{} (sign 5)", sign)), "positive");
+        assert_eq!(eval("(cond [false 1])"), "nil");
+
+        let name = "(func name [n] (case n [1 \"one\"] [2 \"two\"] [else \"many\"]))";
+
+        assert_eq!(eval(&format!("{} (name 2)", name)), "two");
+        assert_eq!(eval(&format!("{} (name 7)", name)), "many");
+    }
+
+    #[test]
+    fn and_or_stop_early() {
+        assert_eq!(eval("(and 1 \"a\" 0 (missing))"), "0");
+        assert_eq!(eval("(or nil false \"a\" (missing))"), "a");
+        assert_eq!(eval("(concat (and) (or))"), "truefalse");
+        assert_eq!(eval("(concat (not nil) (not 1))"), "truefalse");
+    }
+
+    #[test]
+    fn function_bodies() {
+        let clamp = "
+            (func clamp [x lo hi]
+                (var x (if (lt x lo) lo x))
+                (if (gt x hi) hi x))";
+
+        assert_eq!(eval(&format!("{} (clamp 15 0 10)", clamp)), "10");
+        assert_eq!(eval(&format!("{} (clamp -5 0 10)", clamp)), "0");
+        assert_eq!(eval("((lambda [x] (print \"\") (mul x 2)) 4)"), "8");
+    }
+
+    #[test]
+    fn let_binds_in_parallel() {
+        assert_eq!(eval("(var a 1) (let [a 2 b a] (add a b))"), "3");
+        assert_eq!(eval("(var a 1) (let* [a 2 b a] (add a b))"), "4");
+    }
+
+    #[test]
+    fn let_bindings_stay_inside() {
+        assert_eq!(eval("(let [a 1] (var c 3) (add a c))"), "4");
+        assert_eq!(eval("(var a 1) (let [a 2] a) (do a)"), "1");
+        assert_eq!(eval("(let [a 1] (var c 3)) (do c)"), "nil");
+    }
+
+    #[test]
+    fn catch_by_kind() {
+        let risky = "
+            (func risky [x]
+                (if (lt x 0) (throw {kind \"negative\" message \"below zero\"}))
+                (add x \"oops\"))";
+
+        let source = format!(
+            "{} (try (risky -1) (catch \"type-mismatch\" e \"wrong\") (catch e (get e \"message\")))",
+            risky
+        );
+        assert_eq!(eval(&source), "below zero");
+
+        let source = format!(
+            "{} (try (risky 1) (catch [\"negative\" \"type-mismatch\"] e (get e \"kind\")))",
+            risky
+        );
+        assert_eq!(eval(&source), "type-mismatch");
+    }
+
+    #[test]
+    fn uncaught_errors_keep_their_kind() {
+        let source = "(try (throw \"boom\") (catch \"other\" e 1))";
+
+        assert!(eval(source).contains("boom"));
+        assert_eq!(
+            eval(&format!("(try {} (catch e (get e \"kind\")))", source)),
+            "error"
+        );
+    }
+
+    #[test]
+    fn thrown_values() {
+        assert_eq!(eval("(try (throw 42) (catch e (get e \"value\")))"), "42");
+        assert_eq!(eval("(try (throw 42) (catch e (get e \"message\")))"), "42");
+    }
+
+    #[test]
+    fn finally_always_runs() {
+        let source = "
+            (var log \"\")
+            (try (throw 1) (catch e nil) (finally (set! log (concat log \"caught \"))))
+            (try 1 (finally (set! log (concat log \"plain\"))))
+            (do log)";
+
+        assert_eq!(eval(source), "caught plain");
+        assert_eq!(eval("(try 1 (finally 2))"), "1");
+    }
+
+    #[test]
+    fn break_passes_through_try() {
+        let source = "(for [i 0 10] (try (if (eq i 3) (break i)) (catch e \"caught\")))";
+
+        assert_eq!(eval(source), "3");
+    }
+
+    #[test]
+    fn quasiquote() {
+        assert_eq!(eval("(eq (quote (f x)) '(f x) `(f ,'x))"), "true");
+        assert_eq!(eval("(var xs [1 2]) (do `(f ,@xs 3))"), "(f 1 2 3)");
+        assert!(eval("`(a ,@1)").contains("type mismatch"));
+    }
+
+    #[test]
+    fn macros() {
+        let unless = "(defmacro unless [test & body] `(if ,test nil (do ,@body)))";
+
+        assert_eq!(eval(&format!("{} (unless false 1 2)", unless)), "2");
+        assert_eq!(eval(&format!("{} (unless true 1)", unless)), "nil");
+        assert_eq!(eval(&format!("{} (typeof unless)", unless)), "macro");
+    }
+
+    #[test]
+    fn gensym_keeps_names_apart() {
+        let source = "
+            (defmacro twice [form]
+                (var v (gensym))
+                `(let [,v ,form] (add ,v ,v)))
+            (var v 1)
+            (twice (add v 4))";
+
+        assert_eq!(eval(source), "10");
+    }
+
+    #[test]
+    fn gensym_counts_per_interpreter() {
+        assert_eq!(eval("(concat (gensym) (gensym \"x\"))"), "g__0x__1");
+        assert_eq!(eval("(let [a 1] (gensym))"), "g__0");
+    }
+
+    #[test]
+    fn macroexpand() {
+        let macros = "
+            (defmacro apply-to [f & args] `(,f ,@args))
+            (defmacro square [x] `(apply-to mul ,x ,x))";
+
+        assert_eq!(eval(&format!("{} (square 4)", macros)), "16");
+        assert_eq!(
+            eval(&format!("{} (macroexpand-1 '(square 4))", macros)),
+            "(apply-to mul 4 4)"
+        );
+        assert_eq!(
+            eval(&format!("{} (macroexpand '(square 4))", macros)),
+            "(mul 4 4)"
+        );
+    }
+
+    #[test]
+    fn read_and_eval() {
+        assert_eq!(eval("(typeof (read \"(mul x 21)\"))"), "expression");
+        assert_eq!(eval("(var x 2) (eval (read \"(mul x 21)\"))"), "42");
+        assert_eq!(
+            eval("(var x 2) (get (eval (read \"{size (add x 1)}\")) \"size\")"),
+            "3"
+        );
+        assert_eq!(eval("(parse \"1 [a b] (add 1 2)\")"), "[1 [a b] (add 1 2)]");
+    }
+
+    #[test]
+    fn read_errors_are_catchable() {
+        assert_eq!(
+            eval("(try (read \"(add 1\") (catch e (get e \"kind\")))"),
+            "parse-error"
+        );
+    }
+
+    #[test]
+    fn objects() {
+        let base = "(var base {name \"Denis\" age (add 10 10)})";
+
+        assert_eq!(
+            eval(&format!(
+                "{} (keys (remove (merge (set base \"city\" \"Moscow\") {{age 21}}) \"name\"))",
+                base
+            )),
+            "[\"age\" \"city\"]"
+        );
+        assert_eq!(
+            eval(&format!(
+                "{} (add (get base \"age\") (get base \"height\" 1))",
+                base
+            )),
+            "21"
+        );
+        assert_eq!(
+            eval("(concat (typeof {}) (has {a 1} \"a\") (get {a 1} \"b\"))"),
+            "objecttruenil"
+        );
+    }
+
+    #[test]
+    fn while_and_continue() {
+        let source = "
+            (var i 0)
+            (var sum 0)
+            (while (lt i 10)
+                (set! i (add i 1))
+                (if (eq i 5) (continue))
+                (set! sum (add sum i)))
+            (do sum)";
+
+        assert_eq!(eval(source), "50");
+    }
+
+    #[test]
+    fn continue_with_a_value() {
+        assert_eq!(
+            eval("(for [x [1 5 2 8 3]] (if (gt x 4) (continue x)))"),
+            "8"
+        );
+        assert_eq!(eval("(for [x [1 5 2]] (if (gt x 4) (continue x)))"), "5");
+        assert_eq!(
+            eval("(var i 0) (while (lt i 3) (set! i (add i 1)) (continue (mul i 10)))"),
+            "30"
+        );
+        assert_eq!(eval("(for [i 0 3] (continue i) (break -1))"), "2");
+        assert_eq!(eval("(for [i 0 3] (continue))"), "nil");
+        assert!(eval("(continue 1 2)").contains("too many arguments"));
+    }
+
+    #[test]
+    fn for_over_lists_and_ranges() {
+        let source = "
+            (var total 0)
+            (for [x [1 2 3]] (set! total (add total x)))
+            (for [i 10 0 (sub 0 2)] (set! total (add total i)))
+            (do total)";
+
+        assert_eq!(eval(source), "36");
+        assert_eq!(
+            eval("(var s \"\") (for [c \"abc\"] (set! s (concat c s))) (do s)"),
+            "cba"
+        );
+        assert!(eval("(for [i 0 1 0] i)").contains("can't be zero"));
+    }
+
+    #[test]
+    fn binding_errors_point_into_the_list() {
+        assert_eq!(
+            eval("(func f [x 1] x)"),
+            "error: type mismatch: expected identifier, found number\n --> <string>:1:12\n  |\n1 | (func f [x 1] x)\n  |            ^"
+        );
+        assert_eq!(
+            eval("(let [a 1\n      2 3] a)"),
+            "error: type mismatch: expected identifier, found number\n --> <string>:2:7\n  |\n2 |       2 3] a)\n  |       ^"
+        );
+
+        let location = |source| eval(source).lines().nth(1).unwrap().to_string();
+
+        assert_eq!(location("(for [i 0 \"ten\"] i)"), " --> <string>:1:11");
+        assert_eq!(location("(for [x 5] x)"), " --> <string>:1:9");
+    }
+
+    #[test]
+    fn break_leaves_with_a_value() {
+        assert_eq!(eval("(loop (break 1000))"), "1000");
+        assert_eq!(eval("(while true (break))"), "nil");
+        assert_eq!(eval("(for [i 0 3] i)"), "nil");
+
+        let source = "
+            (func first_over [limit]
+                (for [i 0 100] (if (gt (mul i i) limit) (break i))))
+            (first_over 50)";
+
+        assert_eq!(eval(source), "8");
+    }
+
+    #[test]
+    fn break_outside_a_loop() {
+        assert!(eval("(func stop [] (break)) (loop (stop))").contains("`break` outside of a loop"));
+    }
+
+    #[test]
+    fn assignment_updates_enclosing_frames() {
+        let source = "
+            (func make [] (var c 0) (lambda [] (set! c (add c 1)) c))
+            (var counter (make))
+            (counter)
+            (counter)";
+
+        assert_eq!(eval(source), "2");
+        assert_eq!(
+            eval("(var total 1) (func bump [n] (set! total (add total n))) (bump 2) (do total)"),
+            "3"
+        );
+    }
+
+    #[test]
+    fn assignment_needs_a_binding() {
+        assert!(eval("(set! missing 1)").contains("undefined variable `missing`"));
+        assert_eq!(
+            eval("(func f [] (set! local 1)) (try (f) (catch e (get e \"kind\")))"),
+            "undefined-variable"
+        );
+    }
+
+    #[test]
+    fn for_binds_in_a_frame_of_its_own() {
+        assert_eq!(eval("(var x 10) (for [x [1 2]] x) (do x)"), "10");
+        assert_eq!(eval("(func f [] (var x 5) (for [x 0 3] x) x) (f)"), "5");
+        assert_eq!(
+            eval("(var seen 0) (for [i 0 3] (var seen i)) (do seen)"),
+            "0"
+        );
+        assert_eq!(
+            eval("(var n 0) (for [i 0 3] (for [j 0 i] (set! n (add n 1)))) (do n)"),
+            "3"
+        );
+    }
+
+    #[test]
+    fn while_and_loop_bind_in_a_frame_of_their_own() {
+        assert_eq!(
+            eval("(var i 0) (while (lt i 3) (var seen i) (set! i (add i 1))) (concat i seen)"),
+            "3nil"
+        );
+        assert_eq!(
+            eval("(var n 0) (loop (var x 1) (set! n (add n x)) (if (eq n 3) (break x)))"),
+            "1"
+        );
+        assert_eq!(eval("(var x 10) (loop (var x 1) (break)) (do x)"), "10");
+        assert_eq!(
+            eval(
+                "(func f [] (var i 0) (while (lt i 2) (var j i) (set! i (add i 1))) (concat i j)) (f)"
+            ),
+            "2nil"
+        );
+    }
+}
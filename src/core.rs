@@ -1,40 +1,233 @@
-use std::io::{self, Write};
-use std::{error::Error, fs};
+use std::{borrow::Cow, collections::HashMap, env, error::Error, fs, path::PathBuf};
 
-use crate::parser::Parser;
+use rustyline::{
+    CompletionType, Config, Context, Editor, Helper,
+    completion::Completer,
+    error::ReadlineError,
+    highlight::{CmdKind, Highlighter, MatchingBracketHighlighter},
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+};
+use uncommon_lisp::{
+    Interpreter,
+    parser::{Parser, is_identifier_char},
+};
+
+use crate::session::{Session, commands};
+
+const PROMPT: &str = "ul> ";
+/// Shown while the input read so far has unclosed brackets
+const CONTINUATION_PROMPT: &str = "..> ";
+
+/// Line editor helper of the REPL: it completes names, hints at the
+/// parameters of functions and highlights the bracket matching the one under
+/// the cursor.
+#[derive(Default)]
+struct ReplHelper {
+    brackets: MatchingBracketHighlighter,
+    /// Names completion offers, sorted
+    names: Vec<String>,
+    /// Parameters of the functions defined in the session, by name
+    parameters: HashMap<String, String>,
+}
+
+impl ReplHelper {
+    /// Picks up what the last input defined.
+    fn refresh(&mut self, session: &Session) {
+        self.names = session.names();
+        self.parameters = session.parameters();
+    }
+}
+
+/// Start of the identifier that ends at `pos`.
+fn word_start(line: &str, pos: usize) -> usize {
+    line[..pos]
+        .char_indices()
+        .rev()
+        .find(|&(_, ch)| !is_identifier_char(ch))
+        .map_or(0, |(i, ch)| i + ch.len_utf8())
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = word_start(line, pos);
+        let word = &line[start..pos];
+
+        if &line[..start] == ":" {
+            let commands = commands()
+                .filter(|command| command[1..].starts_with(word))
+                .map(str::to_string);
+
+            return Ok((0, commands.collect()));
+        }
+
+        if word.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+
+        let names = self
+            .names
+            .iter()
+            .filter(|name| name.starts_with(word))
+            .cloned();
+
+        Ok((start, names.collect()))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+
+    /// Shows the parameters after the name of a function called at the end
+    /// of the line: `(clamp` hints ` x lo hi)`.
+    fn hint(&self, line: &str, pos: usize, _: &Context<'_>) -> Option<String> {
+        if pos < line.len() {
+            return None;
+        }
+
+        let start = word_start(line, pos);
+
+        if !line[..start].ends_with('(') {
+            return None;
+        }
+
+        match self.parameters.get(&line[start..pos])?.as_str() {
+            "" => Some(")".to_string()),
+            params => Some(format!(" {})", params)),
+        }
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        self.brackets.highlight(line, pos)
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize, kind: CmdKind) -> bool {
+        self.brackets.highlight_char(line, pos, kind)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// File the history is kept in between sessions, `~/.ul_history`.
+fn history_file() -> Option<PathBuf> {
+    env::home_dir().map(|home| home.join(".ul_history"))
+}
+
+/// Whether `input` stops inside a bracket and the next line may close it.
+/// Meta-commands continue the same way when their argument does.
+fn is_incomplete(input: &str) -> bool {
+    let source = match input.strip_prefix(':') {
+        Some(command) => command
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, arg)| arg),
+        None => input,
+    };
+
+    Parser::new(source)
+        .parse_expressions()
+        .is_err_and(|error| error.is_incomplete())
+}
+
+/// Reads expressions until `quit` or Ctrl-D, evaluating each one and showing
+/// its value. Input with unclosed brackets continues on the next line, Ctrl-C
+/// drops the input read so far. Lines starting with `:` are meta-commands,
+/// see `:help`.
+pub fn repl(interpreter: Interpreter) -> rustyline::Result<()> {
+    let mut session = Session::new(interpreter);
+    // Listing every candidate answers "is it `le` or `lte`?" at once
+    let config = Config::builder()
+        .completion_type(CompletionType::List)
+        .build();
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::with_config(config)?;
+    let mut helper = ReplHelper::default();
+    helper.refresh(&session);
+    editor.set_helper(Some(helper));
+
+    let history = history_file();
+
+    if let Some(path) = &history {
+        // There's no history yet on the first run
+        let _ = editor.load_history(path);
+    }
+
+    let mut input = String::new();
 
-pub fn repl() -> io::Result<()> {
     loop {
-        print!("ul> ");
-        io::stdout().flush()?;
+        let prompt = match input.is_empty() {
+            true => PROMPT,
+            false => CONTINUATION_PROMPT,
+        };
+
+        match editor.readline(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+            }
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error),
+        }
+
+        if is_incomplete(&input) {
+            continue;
+        }
+
+        let source = std::mem::take(&mut input);
+        let source = source.trim();
+
+        if source.is_empty() {
+            continue;
+        }
 
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
+        editor.add_history_entry(source)?;
 
-        if input.trim() == "quit" {
+        if source == "quit" {
             break;
         }
 
-        match Parser::new(&input).parse_expressions() {
-            Ok(expressions) => {
-                for e in expressions {
-                    match e.execute(None) {
-                        Ok(result) => println!("{}", result.to_string()),
-                        Err(error) => println!("RuntimeError: {}", error),
-                    }
-                }
+        if source.starts_with(':') {
+            if !session.command(source) {
+                break;
             }
-            Err(error) => println!("ParseError: {}", error),
+        } else {
+            session.run(source);
+        }
+
+        if let Some(helper) = editor.helper_mut() {
+            helper.refresh(&session);
         }
     }
 
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+
     Ok(())
 }
 
 pub fn tokenize_file(path: &str) -> Result<(), Box<dyn Error>> {
     let file_content = fs::read_to_string(path)?;
 
-    Parser::new(&file_content)
+    Parser::with_name(path, &file_content)
         .parse_expressions()?
         .into_iter()
         .for_each(|token| println!("{:?}", token));
@@ -42,13 +235,80 @@ pub fn tokenize_file(path: &str) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub fn run_file(path: &str) -> Result<(), Box<dyn Error>> {
-    let file_content = fs::read_to_string(path)?;
-    let expressions = Parser::new(&file_content).parse_expressions()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for e in expressions {
-        e.execute(None)?;
+    /// A helper knowing what `source` defines.
+    fn helper(source: &str) -> ReplHelper {
+        let mut session = Session::new(Interpreter::new());
+        session.run(source);
+
+        let mut helper = ReplHelper::default();
+        helper.refresh(&session);
+        helper
     }
 
-    Ok(())
+    #[test]
+    fn word_start_stops_at_delimiters() {
+        assert_eq!(word_start("(add fo", 7), 5);
+        assert_eq!(word_start("square", 6), 0);
+        assert_eq!(word_start("(set! x", 5), 1);
+        assert_eq!(word_start("(print \"日本\" ab", 18), 16);
+        assert_eq!(word_start("(", 1), 1);
+    }
+
+    #[test]
+    fn completion() {
+        let helper = helper("(func square [x] (mul x x))");
+        let history = DefaultHistory::new();
+        let context = Context::new(&history);
+
+        assert_eq!(
+            helper.complete("(squ", 4, &context).unwrap(),
+            (1, vec!["square".to_string()])
+        );
+        assert_eq!(helper.complete("(", 1, &context).unwrap(), (1, vec![]));
+    }
+
+    #[test]
+    fn command_completion() {
+        let helper = helper("");
+        let history = DefaultHistory::new();
+        let context = Context::new(&history);
+
+        assert_eq!(
+            helper.complete(":he", 3, &context).unwrap(),
+            (0, vec![":help".to_string()])
+        );
+        assert_eq!(
+            helper.complete(":t", 2, &context).unwrap(),
+            (0, vec![":type".to_string(), ":time".to_string()])
+        );
+    }
+
+    #[test]
+    fn signature_hint() {
+        let helper = helper("(func clamp [x lo hi] (min (max x lo) hi)) (func now [] 1)");
+        let history = DefaultHistory::new();
+        let context = Context::new(&history);
+
+        assert_eq!(
+            helper.hint("(clamp", 6, &context),
+            Some(" x lo hi)".to_string())
+        );
+        assert_eq!(helper.hint("(now", 4, &context), Some(")".to_string()));
+        assert_eq!(helper.hint("(clamp 1", 8, &context), None);
+        assert_eq!(helper.hint("(clamp", 3, &context), None);
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert!(is_incomplete("(add 1"));
+        assert!(!is_incomplete("(add 1 2)"));
+        assert!(is_incomplete(":type (add 1"));
+        assert!(is_incomplete(":time (loop\n  (break"));
+        assert!(!is_incomplete(":type (add 1 2)"));
+        assert!(!is_incomplete(":help"));
+    }
 }
@@ -1,49 +1,386 @@
-use std::{error::Error, fmt::Display};
+use std::{error, fmt::Display, io};
+
+use crate::{
+    source::{Span, location, render},
+    token::Token,
+};
 
 #[derive(Debug, PartialEq)]
 pub enum ParseError {
-    UnterminatedString {
-        line: usize,
-        position: usize,
-    },
-    UnknownToken {
-        line: usize,
-        position: usize,
-        ch: char,
-    },
-    IncompleteExpression {
-        line: usize,
-        position: usize,
-    },
-    IncompleteList {
-        line: usize,
-        position: usize,
-    },
-    ExpectedExpression,
+    UnterminatedString { span: Span },
+    UnknownToken { span: Span, ch: char },
+    InvalidNumber { span: Span, text: String },
+    IncompleteExpression { span: Span },
+    IncompleteList { span: Span },
+    IncompleteObject { span: Span },
+    InvalidObjectKey { span: Span, found: String },
+    MissingObjectValue { span: Span, key: String },
+    ExpectedExpression { span: Span },
+}
+
+impl ParseError {
+    pub fn span(&self) -> &Span {
+        match self {
+            Self::UnterminatedString { span }
+            | Self::UnknownToken { span, .. }
+            | Self::InvalidNumber { span, .. }
+            | Self::IncompleteExpression { span }
+            | Self::IncompleteList { span }
+            | Self::IncompleteObject { span }
+            | Self::InvalidObjectKey { span, .. }
+            | Self::MissingObjectValue { span, .. }
+            | Self::ExpectedExpression { span } => span,
+        }
+    }
+
+    /// Whether the input ended inside a bracket, so more input may complete
+    /// it.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(
+            self,
+            Self::IncompleteExpression { .. }
+                | Self::IncompleteList { .. }
+                | Self::IncompleteObject { .. }
+        )
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::UnterminatedString { .. } => "unterminated string".to_string(),
+            Self::UnknownToken { ch, .. } => format!("unknown token `{}`", ch),
+            Self::InvalidNumber { text, .. } => format!("invalid number `{}`", text),
+            Self::IncompleteExpression { .. } => "incomplete expression, missing `)`".to_string(),
+            Self::IncompleteList { .. } => "incomplete list, missing `]`".to_string(),
+            Self::IncompleteObject { .. } => "incomplete object, missing `}`".to_string(),
+            Self::InvalidObjectKey { found, .. } => format!(
+                "object keys must be identifiers, strings, numbers or bools, found {}",
+                found
+            ),
+            Self::MissingObjectValue { key, .. } => {
+                format!("missing value for object key `{}`", key)
+            }
+            Self::ExpectedExpression { .. } => "expected an expression".to_string(),
+        }
+    }
 }
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        write!(f, "{}", render(&self.message(), self.span()))
     }
 }
 
-impl Error for ParseError {}
+impl error::Error for ParseError {}
 
 #[derive(Debug, PartialEq)]
 pub enum RuntimeError {
     // InvalidExpression,
-    TypeMismatch { expected: String, found: String },
-    NotEnoughArgs { min: usize },
-    TooMuchArgs { max: usize },
+    TypeMismatch {
+        expected: String,
+        found: String,
+    },
+    NotEnoughArgs {
+        min: usize,
+    },
+    TooMuchArgs {
+        max: usize,
+    },
     UndefinedFunction(String),
-    InvalidArgCount { expected: usize, got: usize },
+    /// `set!` of a name no frame binds
+    UndefinedVariable(String),
+    InvalidArgCount {
+        expected: usize,
+        got: usize,
+    },
+    InvalidValue(String),
+    OutsideLoop(String),
+    /// Source read by `read` or `load` doesn't parse
+    Parse(ParseError),
+    /// A file the script asked for can't be read
+    Io(String),
+    /// Files importing each other, the first one repeated at the end
+    ImportCycle(Vec<String>),
+    /// Evaluation nested deeper than `limit` levels, usually runaway recursion
+    StackOverflow {
+        limit: usize,
+    },
+    /// Raised by the script with `throw`
+    Thrown(Box<Token>),
+
+    /// Error raised while executing the expression at `span`, `trace` lists
+    /// the user function calls it escaped from, innermost first
+    Located {
+        span: Span,
+        error: Box<RuntimeError>,
+        trace: Vec<CallFrame>,
+    },
+
+    // Control flow signals, unwound up to the nearest enclosing loop
+    Break(Box<Token>),
+    Continue(Box<Token>),
+}
+
+impl RuntimeError {
+    /// Turns a `break`/`continue` that left its loop into a real error.
+    pub fn escaped(self) -> Self {
+        match self {
+            Self::Break(_) => Self::OutsideLoop("break".to_string()),
+            Self::Continue(_) => Self::OutsideLoop("continue".to_string()),
+            error => error,
+        }
+    }
+
+    /// Attaches the location of the innermost failing expression. An already
+    /// located error only uses `span` as the call site of the function call it
+    /// just escaped from. Control flow signals pass through untouched.
+    pub fn located(self, span: Span) -> Self {
+        match self {
+            Self::Located {
+                span: origin,
+                error,
+                mut trace,
+            } => {
+                if let Some(
+                    frame @ CallFrame {
+                        call_site: None, ..
+                    },
+                ) = trace.last_mut()
+                {
+                    frame.call_site = Some(span);
+                }
+
+                Self::Located {
+                    span: origin,
+                    error,
+                    trace,
+                }
+            }
+            Self::Break(_) | Self::Continue(_) => self,
+            error => Self::Located {
+                span,
+                error: Box::new(error),
+                trace: Vec::new(),
+            },
+        }
+    }
+
+    /// Like [`RuntimeError::located`], for locations that may be unknown.
+    pub fn located_at(self, span: Option<Span>) -> Self {
+        match span {
+            Some(span) => self.located(span),
+            None => self,
+        }
+    }
+
+    /// Records that the error escaped from the body of a user function.
+    pub fn in_call(mut self, frame: CallFrame) -> Self {
+        if let Self::Located { trace, .. } = &mut self {
+            trace.push(frame);
+        }
+
+        self
+    }
+
+    /// Unwinds the error out of the body of the function described by `frame`.
+    pub fn unwound(self, frame: CallFrame) -> Self {
+        let call_site = frame.call_site.clone();
+        let error = self.escaped().in_call(frame);
+
+        match call_site {
+            Some(span) => error.located(span),
+            None => error,
+        }
+    }
+
+    pub fn trace(&self) -> &[CallFrame] {
+        match self {
+            Self::Located { trace, .. } => trace,
+            _ => &[],
+        }
+    }
+
+    /// The error without its location.
+    pub fn kind(&self) -> &RuntimeError {
+        match self {
+            Self::Located { error, .. } => error.kind(),
+            error => error,
+        }
+    }
+
+    /// Name scripts use to tell errors apart in `catch`. Thrown objects can
+    /// pick their own with a `kind` key.
+    pub fn kind_name(&self) -> String {
+        match self.kind() {
+            Self::TypeMismatch { .. } => "type-mismatch",
+            Self::NotEnoughArgs { .. } => "not-enough-args",
+            Self::TooMuchArgs { .. } => "too-many-args",
+            Self::UndefinedFunction(_) => "undefined-function",
+            Self::UndefinedVariable(_) => "undefined-variable",
+            Self::InvalidArgCount { .. } => "invalid-arg-count",
+            Self::InvalidValue(_) => "invalid-value",
+            Self::OutsideLoop(_) => "outside-loop",
+            Self::Parse(_) => "parse-error",
+            Self::Io(_) => "io-error",
+            Self::ImportCycle(_) => "import-cycle",
+            Self::StackOverflow { .. } => "stack-overflow",
+            Self::Thrown(value) => match &**value {
+                Token::Object(object) => match object.get("kind") {
+                    Some(Token::String(kind)) => return kind.clone(),
+                    _ => "error",
+                },
+                _ => "error",
+            },
+            Self::Break(_) | Self::Continue(_) => "control-flow",
+            Self::Located { .. } => unreachable!(),
+        }
+        .to_string()
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Self::Located { span, .. } => Some(span),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self.kind() {
+            Self::TypeMismatch { expected, found } => {
+                format!("type mismatch: expected {}, found {}", expected, found)
+            }
+            Self::NotEnoughArgs { min } => {
+                format!("not enough arguments, expected at least {}", min)
+            }
+            Self::TooMuchArgs { max } => format!("too many arguments, expected at most {}", max),
+            Self::UndefinedFunction(name) => format!("undefined function `{}`", name),
+            Self::UndefinedVariable(name) => format!("undefined variable `{}`", name),
+            Self::InvalidArgCount { expected, got } => {
+                format!("expected {} arguments, got {}", expected, got)
+            }
+            Self::InvalidValue(message) => format!("invalid value: {}", message),
+            Self::OutsideLoop(keyword) => format!("`{}` outside of a loop", keyword),
+            Self::Parse(error) => match location(error.span()) {
+                Some(location) => format!("{} at {}", error.message(), location),
+                None => error.message(),
+            },
+            Self::Io(message) => format!("can't read {}", message),
+            Self::ImportCycle(files) => format!("import cycle: {}", files.join(" -> ")),
+            Self::StackOverflow { limit } => {
+                format!(
+                    "stack overflow, evaluation nested deeper than {} levels",
+                    limit
+                )
+            }
+            Self::Thrown(value) => match &**value {
+                Token::Object(object) => match object.get("message") {
+                    Some(message) => message.to_string(),
+                    None => object.to_string(),
+                },
+                value => value.to_string(),
+            },
+            Self::Break(_) => "unhandled `break`".to_string(),
+            Self::Continue(_) => "unhandled `continue`".to_string(),
+            Self::Located { .. } => unreachable!(),
+        }
+    }
 }
 
+/// How many innermost and outermost frames of a long backtrace are printed
+const TRACE_EDGE: usize = 10;
+
 impl Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        write!(
+            f,
+            "{}",
+            render(&self.message(), self.span().unwrap_or(&Span::default()))
+        )?;
+
+        let trace = self.trace();
+
+        if trace.is_empty() {
+            return Ok(());
+        }
+
+        write!(f, "\nstack backtrace:")?;
+
+        for (i, frame) in trace.iter().enumerate() {
+            if trace.len() > TRACE_EDGE * 2 && i == TRACE_EDGE {
+                write!(f, "\n  ... {} frames omitted", trace.len() - TRACE_EDGE * 2)?;
+            }
+
+            if trace.len() > TRACE_EDGE * 2 && (TRACE_EDGE..trace.len() - TRACE_EDGE).contains(&i) {
+                continue;
+            }
+
+            write!(f, "\n  {:>3}: {}", i, frame)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl error::Error for RuntimeError {}
+
+/// Anything that can stop a program: reading it, parsing it or running it.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Parse(ParseError),
+    Runtime(RuntimeError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "error: {}", error),
+            Self::Parse(error) => write!(f, "{}", error),
+            Self::Runtime(error) => write!(f, "{}", error),
+        }
     }
 }
 
-impl Error for RuntimeError {}
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Self {
+        Self::Parse(error)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(error: RuntimeError) -> Self {
+        Self::Runtime(error)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CallFrame {
+    pub function: String,
+    pub args: Vec<(String, Token)>,
+    pub call_site: Option<Span>,
+}
+
+impl Display for CallFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+
+        write!(f, "{} [{}]", self.function, args.join(" "))?;
+
+        if let Some(location) = self.call_site.as_ref().and_then(location) {
+            write!(f, " at {}", location)?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,19 +1,25 @@
 use crate::{
     errors::RuntimeError,
-    token::{Expression, Identifier, List, Token},
+    scope::{Function, Scope},
+    token::{Expression, Identifier, List, Number, Object, Token},
 };
 
+/// Conversion of an evaluated value into a Rust type.
 pub trait Extractable: Sized {
-    fn extract(token: Token, maybe_prefix: Option<String>) -> Result<Self, RuntimeError>;
+    fn extract(token: Token, scope: &Scope) -> Result<Self, RuntimeError>;
 }
 
-impl Extractable for f64 {
-    fn extract(token: Token, maybe_prefix: Option<String>) -> Result<Self, RuntimeError> {
+/// Any value
+impl Extractable for Token {
+    fn extract(token: Token, _: &Scope) -> Result<Self, RuntimeError> {
+        Ok(token)
+    }
+}
+
+impl Extractable for Number {
+    fn extract(token: Token, _: &Scope) -> Result<Self, RuntimeError> {
         match token {
             Token::Number(value) => Ok(value),
-            Token::Identifier(_) | Token::Expression(_) => {
-                Self::extract(token.into_value(maybe_prefix.clone())?, maybe_prefix)
-            }
             _ => Err(RuntimeError::TypeMismatch {
                 expected: "number".to_string(),
                 found: token.as_type().to_string(),
@@ -22,34 +28,51 @@ impl Extractable for f64 {
     }
 }
 
-impl Extractable for String {
-    fn extract(token: Token, maybe_prefix: Option<String>) -> Result<Self, RuntimeError> {
-        match token {
-            Token::Identifier(_) | Token::Expression(_) => {
-                Self::extract(token.into_value(maybe_prefix.clone())?, maybe_prefix)
-            }
-            _ => Ok(token.to_string()),
+/// Any number, integers are converted
+impl Extractable for f64 {
+    fn extract(token: Token, scope: &Scope) -> Result<Self, RuntimeError> {
+        Number::extract(token, scope).map(|value| value.to_f64())
+    }
+}
+
+impl Extractable for i64 {
+    fn extract(token: Token, scope: &Scope) -> Result<Self, RuntimeError> {
+        match Number::extract(token, scope)? {
+            Number::Int(value) => Ok(value),
+            Number::Big(value) => Err(RuntimeError::InvalidValue(format!(
+                "{} doesn't fit into a 64-bit integer",
+                value
+            ))),
+            Number::Float(value) => Err(RuntimeError::TypeMismatch {
+                expected: "integer".to_string(),
+                found: format!("float {}", Number::Float(value)),
+            }),
         }
     }
 }
 
+impl Extractable for String {
+    fn extract(token: Token, _: &Scope) -> Result<Self, RuntimeError> {
+        Ok(token.to_string())
+    }
+}
+
 impl Extractable for bool {
-    fn extract(token: Token, maybe_prefix: Option<String>) -> Result<Self, RuntimeError> {
+    fn extract(token: Token, _: &Scope) -> Result<Self, RuntimeError> {
         Ok(match token {
-            Token::Number(v) => v != 0.0,
-            Token::String(v) => v.len() > 0,
+            Token::Number(v) => !v.is_zero(),
+            Token::String(v) => !v.is_empty(),
             Token::Bool(value) => value,
             Token::Nil => false,
-            Token::List(list) => list.len() > 0,
-            Token::Identifier(_) | Token::Expression(_) => {
-                Self::extract(token.into_value(maybe_prefix.clone())?, maybe_prefix)?
-            }
+            Token::List(list) => !list.is_empty(),
+            Token::Object(object) => !object.is_empty(),
+            Token::Function(_) | Token::Identifier(_) | Token::Expression(_) => true,
         })
     }
 }
 
 impl Extractable for Identifier {
-    fn extract(token: Token, _: Option<String>) -> Result<Self, RuntimeError> {
+    fn extract(token: Token, _: &Scope) -> Result<Self, RuntimeError> {
         match token {
             Token::Identifier(id) => Ok(id),
             _ => Err(RuntimeError::TypeMismatch {
@@ -61,7 +84,7 @@ impl Extractable for Identifier {
 }
 
 impl Extractable for List {
-    fn extract(token: Token, _: Option<String>) -> Result<Self, RuntimeError> {
+    fn extract(token: Token, _: &Scope) -> Result<Self, RuntimeError> {
         match token {
             Token::List(id) => Ok(id),
             _ => Err(RuntimeError::TypeMismatch {
@@ -72,8 +95,20 @@ impl Extractable for List {
     }
 }
 
+impl Extractable for Object {
+    fn extract(token: Token, _: &Scope) -> Result<Self, RuntimeError> {
+        match token {
+            Token::Object(object) => Ok(object),
+            token => Err(RuntimeError::TypeMismatch {
+                expected: "object".to_string(),
+                found: token.as_type(),
+            }),
+        }
+    }
+}
+
 impl Extractable for Expression {
-    fn extract(token: Token, _: Option<String>) -> Result<Self, RuntimeError> {
+    fn extract(token: Token, _: &Scope) -> Result<Self, RuntimeError> {
         match token {
             Token::Expression(e) => Ok(e),
             _ => Err(RuntimeError::TypeMismatch {
@@ -83,3 +118,15 @@ impl Extractable for Expression {
         }
     }
 }
+
+impl Extractable for Function {
+    fn extract(token: Token, _: &Scope) -> Result<Self, RuntimeError> {
+        match token {
+            Token::Function(f) => Ok(f),
+            _ => Err(RuntimeError::TypeMismatch {
+                expected: "function".to_string(),
+                found: token.as_type(),
+            }),
+        }
+    }
+}
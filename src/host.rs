@@ -0,0 +1,220 @@
+use std::{
+    fmt::{Debug, Display},
+    rc::Rc,
+};
+
+use crate::{
+    builtins::BuiltinFunc,
+    errors::RuntimeError,
+    extractor::Extractable,
+    scope::Scope,
+    token::{List, Token},
+};
+
+type NativeFn = dyn Fn(List, &Scope) -> Result<Token, RuntimeError>;
+
+/// How many arguments a builtin accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    /// `None` for variadic builtins
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn exact(n: usize) -> Self {
+        Self {
+            min: n,
+            max: Some(n),
+        }
+    }
+
+    pub fn at_least(n: usize) -> Self {
+        Self { min: n, max: None }
+    }
+
+    pub fn check(&self, got: usize) -> Result<(), RuntimeError> {
+        match self.max {
+            Some(max) if max == self.min && got != max => {
+                Err(RuntimeError::InvalidArgCount { expected: max, got })
+            }
+            Some(max) if got > max => Err(RuntimeError::TooMuchArgs { max }),
+            _ if got < self.min => Err(RuntimeError::NotEnoughArgs { min: self.min }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{}..{}", self.min, max),
+            None => write!(f, "{}+", self.min),
+        }
+    }
+}
+
+/// A function implemented in Rust, either one of the standard builtins or a
+/// closure registered by the host program.
+#[derive(Clone)]
+pub struct Builtin {
+    func: Rc<NativeFn>,
+    arity: Arity,
+    doc: Option<String>,
+}
+
+impl Builtin {
+    /// Wraps a standard builtin, it checks the number of its arguments itself.
+    pub fn native(func: BuiltinFunc) -> Self {
+        Self {
+            func: Rc::new(func),
+            arity: Arity::at_least(0),
+            doc: None,
+        }
+    }
+
+    /// Wraps a Rust closure, its arguments are converted to the closure's
+    /// parameter types before the call.
+    pub fn host<Args, F: HostFunction<Args>>(func: F) -> Self {
+        Self {
+            func: Rc::new(move |args: List, scope: &Scope| func.call(args, scope)),
+            arity: Arity::exact(F::ARITY),
+            doc: None,
+        }
+    }
+
+    pub fn with_doc(mut self, doc: &str) -> Self {
+        self.doc = Some(doc.to_string());
+        self
+    }
+
+    pub fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    pub fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+
+    pub fn call(&self, args: List, scope: &Scope) -> Result<Token, RuntimeError> {
+        self.arity.check(args.len())?;
+        (self.func)(args, scope)
+    }
+}
+
+/// Builtins are equal when they wrap the same function.
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+impl Debug for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Builtin({})", self.arity)
+    }
+}
+
+/// What a host closure may return: any value convertible into a [`Token`],
+/// or a `Result` of one to report errors to the script.
+pub trait HostResult {
+    fn into_result(self) -> Result<Token, RuntimeError>;
+}
+
+impl<T: Into<Token>> HostResult for T {
+    fn into_result(self) -> Result<Token, RuntimeError> {
+        Ok(self.into())
+    }
+}
+
+impl<T: Into<Token>> HostResult for Result<T, RuntimeError> {
+    fn into_result(self) -> Result<Token, RuntimeError> {
+        self.map(Into::into)
+    }
+}
+
+/// Rust closures callable from scripts. Implemented for `Fn`s of up to six
+/// arguments whose types are [`Extractable`], `Args` is the tuple of them.
+pub trait HostFunction<Args>: 'static {
+    const ARITY: usize;
+
+    /// Calls the closure with `args` already checked against `ARITY`.
+    fn call(&self, args: List, scope: &Scope) -> Result<Token, RuntimeError>;
+}
+
+macro_rules! host_function {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg),*> HostFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: HostResult,
+            $($arg: Extractable,)*
+        {
+            const ARITY: usize = <[&str]>::len(&[$(stringify!($arg)),*]);
+
+            #[allow(unused_variables, unused_mut)]
+            fn call(&self, args: List, scope: &Scope) -> Result<Token, RuntimeError> {
+                let mut args = args.0.into_iter();
+
+                self($(args.next().unwrap().extract::<$arg>(scope)?),*)
+                    .into_result()
+            }
+        }
+    };
+}
+
+host_function!();
+host_function!(A);
+host_function!(A, B);
+host_function!(A, B, C);
+host_function!(A, B, C, D);
+host_function!(A, B, C, D, E);
+host_function!(A, B, C, D, E, G);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Number;
+
+    #[test]
+    fn arity() {
+        assert_eq!(
+            Arity::exact(2).check(3),
+            Err(RuntimeError::InvalidArgCount {
+                expected: 2,
+                got: 3
+            })
+        );
+        assert_eq!(
+            Arity::at_least(1).check(0),
+            Err(RuntimeError::NotEnoughArgs { min: 1 })
+        );
+        assert_eq!(Arity::at_least(1).check(5), Ok(()));
+        assert_eq!(Arity::exact(0).to_string(), "0");
+    }
+
+    #[test]
+    fn typed_arguments() {
+        let scope = Scope::new();
+
+        let repeat = Builtin::host(|s: String, n: f64| s.repeat(n as usize));
+        let args = List::from_iterable([
+            Token::String("ab".to_string()),
+            Token::Number(Number::Int(2)),
+        ]);
+
+        assert_eq!(repeat.arity(), Arity::exact(2));
+        assert_eq!(
+            repeat.call(args, &scope),
+            Ok(Token::String("abab".to_string()))
+        );
+        assert_eq!(
+            repeat.call(List::from_iterable([Token::Nil]), &scope),
+            Err(RuntimeError::InvalidArgCount {
+                expected: 2,
+                got: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use std::{fs, path::Path};
+
+use crate::{
+    builtins::SPECIAL_FORMS,
+    errors::{Error, RuntimeError},
+    host::{Builtin, HostFunction},
+    modules,
+    parser::Parser,
+    scope::{Function, Scope},
+    stdlib::math::{DivisionByZero, division_functions},
+    token::{Expression, Token},
+    vm::Vm,
+};
+
+/// How top-level expressions get evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// Walks the expression tree directly
+    #[default]
+    Tree,
+    /// Compiles to bytecode and runs it on a stack machine
+    Vm,
+}
+
+/// A running program. Every interpreter has its own globals, functions and
+/// builtins, so several of them can live side by side in one process.
+///
+/// Deep recursion in the tree-walker takes host stack: run the interpreter on
+/// a thread with [`STACK_SIZE`](crate::token::STACK_SIZE) bytes of it, so
+/// runaway recursion stops with a `stack-overflow` error instead of aborting
+/// the process.
+pub struct Interpreter {
+    scope: Scope,
+    engine: Engine,
+    vm: Vm,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::with_engine(Engine::default())
+    }
+
+    pub fn with_engine(engine: Engine) -> Self {
+        Self {
+            scope: Scope::new(),
+            engine,
+            vm: Vm::new(),
+        }
+    }
+
+    /// Evaluates every expression in `source` and returns the value of the
+    /// last one.
+    pub fn eval_str(&mut self, source: &str) -> Result<Token, Error> {
+        self.eval_source("<string>", source)
+    }
+
+    /// Evaluates a file, modules it imports are looked up next to it first.
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Token, Error> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        let file = path.canonicalize()?;
+        let scope = self.scope.clone();
+
+        modules::run_file(&scope, &file, || {
+            self.eval_source(&path.display().to_string(), &source)
+        })
+    }
+
+    /// Like [`Interpreter::eval_str`], diagnostics refer to the source as
+    /// `name`.
+    pub fn eval_source(&mut self, name: &str, source: &str) -> Result<Token, Error> {
+        let expressions = Parser::with_name(name, source).parse_expressions()?;
+        let mut value = Token::Nil;
+
+        for e in expressions {
+            value = self.eval_expression(&e)?;
+        }
+
+        Ok(value)
+    }
+
+    /// Evaluates one parsed top-level expression.
+    pub fn eval_expression(&mut self, expression: &Expression) -> Result<Token, Error> {
+        let value = match self.engine {
+            Engine::Tree => expression.execute(&self.scope),
+            Engine::Vm => self.vm.execute(expression, &self.scope),
+        }
+        .map_err(RuntimeError::escaped)?;
+
+        Ok(value)
+    }
+
+    /// Value of a global variable or function, nil when it isn't defined.
+    pub fn get_global(&self, name: &str) -> Token {
+        self.scope.get_variable(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Token) {
+        self.scope.set_variable(name, value);
+    }
+
+    /// Makes a Rust closure callable from scripts as `name`. Its arguments
+    /// are checked against its parameter count, evaluated and converted with
+    /// [`Extractable`](crate::extractor::Extractable), e.g.
+    /// `interpreter.register("repeat", |s: String, n: f64| s.repeat(n as usize))`.
+    pub fn register<Args>(&mut self, name: &str, func: impl HostFunction<Args>) {
+        self.define_builtin(name, Builtin::host(func));
+    }
+
+    /// Like [`Interpreter::register`], with a docstring for the function.
+    pub fn register_with_doc<Args>(
+        &mut self,
+        name: &str,
+        doc: &str,
+        func: impl HostFunction<Args>,
+    ) {
+        self.define_builtin(name, Builtin::host(func).with_doc(doc));
+    }
+
+    pub fn define_builtin(&mut self, name: &str, builtin: Builtin) {
+        self.scope.set_builtin(name, builtin);
+        self.vm.clear_cache();
+    }
+
+    /// Chooses between an error and an infinite result for divisions by
+    /// zero, an error is the default.
+    pub fn set_division_by_zero(&mut self, mode: DivisionByZero) {
+        for (name, func) in division_functions(mode) {
+            self.define_builtin(name, Builtin::native(func));
+        }
+    }
+
+    pub fn builtin(&self, name: &str) -> Option<Builtin> {
+        self.scope.get_builtin(name)
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Global variables, sorted by name.
+    pub fn globals(&self) -> Vec<(String, Token)> {
+        self.scope.variables()
+    }
+
+    /// Functions declared with `func` at the top level, sorted by name.
+    pub fn functions(&self) -> Vec<Function> {
+        self.scope.functions()
+    }
+
+    /// Macros declared with `defmacro` at the top level, sorted by name.
+    pub fn macros(&self) -> Vec<Function> {
+        self.scope.macros()
+    }
+
+    /// Names of the builtins, sorted.
+    pub fn builtin_names(&self) -> Vec<String> {
+        self.scope.builtin_names()
+    }
+
+    /// Whether `name` is syntax of the language like `if` or `func`.
+    pub fn is_special_form(name: &str) -> bool {
+        SPECIAL_FORMS.contains_key(name)
+    }
+
+    /// Names of the special forms, sorted.
+    pub fn special_forms() -> Vec<String> {
+        let mut names: Vec<String> = SPECIAL_FORMS.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::token::Number;
+
+    #[test]
+    fn globals() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("base", Token::Number(Number::Int(40)));
+
+        let value = interpreter
+            .eval_str("(var answer (add base 2)) (func double [x] (mul x 2))")
+            .unwrap();
+
+        assert_eq!(value, Token::Nil);
+        assert_eq!(
+            interpreter.get_global("answer"),
+            Token::Number(Number::Int(42))
+        );
+        assert_eq!(interpreter.get_global("double").as_type(), "function");
+        assert_eq!(interpreter.get_global("missing"), Token::Nil);
+    }
+
+    #[test]
+    fn definitions() {
+        let mut interpreter = Interpreter::new();
+
+        interpreter
+            .eval_str(
+                "(var b 2) (var a 1)
+                 (func half [x] \"Halves x.\" (div x 2))
+                 (func id [x] x)
+                 (defmacro ignore [& forms] nil)",
+            )
+            .unwrap();
+
+        let names: Vec<_> = interpreter.globals().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, ["a", "b"]);
+
+        let functions = interpreter.functions();
+        assert_eq!(functions[0].name(), "half");
+        assert_eq!(functions[0].doc(), Some("Halves x."));
+        assert_eq!(functions[1].doc(), None);
+        assert_eq!(interpreter.macros()[0].rest(), Some("forms"));
+        assert!(Interpreter::is_special_form("defmacro"));
+        assert!(!Interpreter::is_special_form("map"));
+        assert!(Interpreter::special_forms().contains(&"let*".to_string()));
+        assert!(interpreter.builtin_names().contains(&"le".to_string()));
+    }
+
+    #[test]
+    fn isolated_interpreters() {
+        let mut first = Interpreter::new();
+        let mut second = Interpreter::with_engine(Engine::Vm);
+
+        first.eval_str("(var x 1) (func f [] (add x 0))").unwrap();
+        second.eval_str("(var x 2)").unwrap();
+
+        assert_eq!(
+            first.eval_str("(f)").unwrap(),
+            Token::Number(Number::Int(1))
+        );
+        assert_eq!(
+            second.eval_str("(add x 1)").unwrap(),
+            Token::Number(Number::Int(3))
+        );
+        assert!(matches!(
+            second.eval_str("(f)"),
+            Err(Error::Runtime(error)) if error.kind() == &RuntimeError::UndefinedFunction("f".to_string())
+        ));
+    }
+
+    #[test]
+    fn host_functions() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        for engine in [Engine::Tree, Engine::Vm] {
+            let mut interpreter = Interpreter::with_engine(engine);
+
+            let sink = log.clone();
+            interpreter.register_with_doc("log", "Remembers a message", move |s: String| {
+                sink.borrow_mut().push(s)
+            });
+            interpreter.register("checked_div", |a: f64, b: f64| {
+                if b == 0.0 {
+                    return Err(RuntimeError::InvalidValue("division by zero".to_string()));
+                }
+
+                Ok(a / b)
+            });
+
+            let value = interpreter
+                .eval_str("(func half [x] (checked_div x 2)) (log (concat \"got \" (half 5)))")
+                .unwrap();
+
+            assert_eq!(value, Token::Nil);
+            assert_eq!(
+                interpreter.builtin("log").unwrap().doc(),
+                Some("Remembers a message")
+            );
+
+            let error = interpreter.eval_str("(checked_div 1 0)").unwrap_err();
+            assert!(matches!(
+                error,
+                Error::Runtime(error) if error.kind() == &RuntimeError::InvalidValue("division by zero".to_string())
+            ));
+
+            let error = interpreter.eval_str("(log)").unwrap_err();
+            assert!(matches!(
+                error,
+                Error::Runtime(error) if error.kind() == &RuntimeError::InvalidArgCount { expected: 1, got: 0 }
+            ));
+        }
+
+        assert_eq!(*log.borrow(), ["got 2.5", "got 2.5"]);
+    }
+
+    #[test]
+    fn load() {
+        let path = std::env::temp_dir().join(format!("ul-load-{}.ul", std::process::id()));
+        fs::write(&path, "(var loaded 1) (func bump [x] (add x loaded))").unwrap();
+
+        let cycle = std::env::temp_dir().join(format!("ul-load-cycle-{}.ul", std::process::id()));
+        let name = cycle.file_name().unwrap().to_string_lossy();
+        fs::write(&cycle, format!("(load \"{}\")", name)).unwrap();
+
+        for engine in [Engine::Tree, Engine::Vm] {
+            let mut interpreter = Interpreter::with_engine(engine);
+            interpreter.set_global("path", Token::String(path.display().to_string()));
+
+            let value = interpreter.eval_str("(load path) (bump 41)").unwrap();
+            assert_eq!(value, Token::Number(Number::Int(42)));
+
+            let error = interpreter
+                .eval_str("(load \"no/such/file.ul\")")
+                .unwrap_err();
+            assert!(matches!(
+                error,
+                Error::Runtime(error) if error.kind_name() == "io-error"
+            ));
+
+            // Found next to the loading file, which is still running
+            interpreter.set_global("path", Token::String(cycle.display().to_string()));
+
+            let error = interpreter.eval_str("(load path)").unwrap_err();
+            assert!(matches!(
+                error,
+                Error::Runtime(error) if error.kind_name() == "import-cycle"
+            ));
+        }
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(cycle).unwrap();
+    }
+
+    #[test]
+    fn modules() {
+        let dir = std::env::temp_dir().join(format!("ul-modules-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+
+        let files = [
+            (
+                "main.ul",
+                "(import \"lib/geometry\" as geo) (var area (geo/square 3))",
+            ),
+            (
+                "lib/geometry.ul",
+                "(import \"math.ul\" [mul2]) (tick) (export square) (func square [x] (mul x x)) (func helper [] 1)",
+            ),
+            ("lib/math.ul", "(func mul2 [x] (mul x 2))"),
+            ("a.ul", "(import \"b\")"),
+            ("b.ul", "(import \"a\")"),
+        ];
+
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+
+        for engine in [Engine::Tree, Engine::Vm] {
+            let runs = Rc::new(RefCell::new(0));
+            let counter = runs.clone();
+
+            let mut interpreter = Interpreter::with_engine(engine);
+            interpreter.register("tick", move || *counter.borrow_mut() += 1);
+
+            interpreter.eval_file(dir.join("main.ul")).unwrap();
+            assert_eq!(
+                interpreter.get_global("area"),
+                Token::Number(Number::Int(9))
+            );
+            assert_eq!(interpreter.get_global("geo/helper"), Token::Nil);
+            assert_eq!(interpreter.get_global("mul2"), Token::Nil);
+
+            let path = dir.join("lib/geometry.ul").display().to_string();
+            interpreter.set_global("path", Token::String(path));
+            let value = interpreter
+                .eval_str("(import path [square]) (square 4)")
+                .unwrap();
+
+            assert_eq!(value, Token::Number(Number::Int(16)));
+            assert_eq!(*runs.borrow(), 1);
+
+            let error = interpreter.eval_file(dir.join("a.ul")).unwrap_err();
+            assert!(matches!(
+                error,
+                Error::Runtime(error) if error.kind_name() == "import-cycle"
+            ));
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        let mut interpreter = Interpreter::new();
+
+        assert!(matches!(
+            interpreter.eval_str("(add 1"),
+            Err(Error::Parse(_))
+        ));
+        assert!(matches!(
+            interpreter.eval_file("no/such/file.ul"),
+            Err(Error::Io(_))
+        ));
+        assert!(matches!(
+            interpreter.eval_str("(break)"),
+            Err(Error::Runtime(_))
+        ));
+    }
+}
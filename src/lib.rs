@@ -0,0 +1,22 @@
+//! Uncommon Lisp as a library: create an [`Interpreter`], feed it source code
+//! and exchange values with it through its globals.
+
+pub mod errors;
+pub mod extractor;
+pub mod host;
+pub mod parser;
+pub mod scope;
+pub mod source;
+pub mod token;
+
+mod builtins;
+mod interpreter;
+mod modules;
+mod stdlib;
+mod utils;
+mod vm;
+
+pub use errors::Error;
+pub use interpreter::{Engine, Interpreter};
+pub use stdlib::math::DivisionByZero;
+pub use token::Token;
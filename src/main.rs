@@ -1,25 +1,48 @@
 #[allow(dead_code)]
 #[allow(unused_variables)]
-use core::{repl, run_file, tokenize_file};
-use std::{env, error::Error};
+use core::{repl, tokenize_file};
+use std::{env, error::Error, process, thread};
+
+use uncommon_lisp::{Engine, Interpreter, token::STACK_SIZE};
 
-mod builtins;
 mod core;
-mod errors;
-mod extractor;
-mod parser;
-mod scope;
-mod token;
-mod utils;
+mod session;
+
+fn main() {
+    // Deep recursion needs more than the stack of the main thread
+    let result = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| run().map_err(|error| error.to_string()))
+        .expect("failed to start the interpreter thread")
+        .join()
+        .unwrap_or_else(|_| process::exit(101));
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut args: Vec<_> = env::args().collect();
+
+    let engine = match args.iter().position(|arg| arg == "--vm") {
+        Some(i) => {
+            args.remove(i);
+            Engine::Vm
+        }
+        None => Engine::Tree,
+    };
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<_> = env::args().collect();
+    let mut interpreter = Interpreter::with_engine(engine);
 
     match args.len() {
-        1 => repl()?,
-        2 => run_file(&args[1])?,
+        1 => repl(interpreter)?,
+        2 => {
+            interpreter.eval_file(&args[1])?;
+        }
         3 if &args[1] == "tokenize" => tokenize_file(&args[2])?,
-        _ => Err("Using: ul <filename>")?,
+        _ => Err("Using: ul [--vm] <filename>")?,
     }
 
     Ok(())
@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{errors::RuntimeError, parser::Parser, scope::Scope, token::Object};
+
+/// Modules of one interpreter, shared by all of its frames
+#[derive(Default)]
+pub struct Modules {
+    /// Exports of every module that finished running, by canonical path
+    loaded: HashMap<PathBuf, Object>,
+    /// Files running right now, the innermost last
+    running: Vec<PathBuf>,
+}
+
+/// Finds the file `(import path)` refers to: next to the importing file, then
+/// in the current directory, then in the directories listed in `UL_PATH`. The
+/// `.ul` extension may be left out.
+fn resolve(path: &str, scope: &Scope) -> Result<PathBuf, RuntimeError> {
+    let mut name = PathBuf::from(path);
+
+    if name.extension().is_none() {
+        name.set_extension("ul");
+    }
+
+    let mut dirs: Vec<PathBuf> = running_dir(scope).into_iter().collect();
+
+    dirs.push(PathBuf::from("."));
+
+    if let Some(paths) = env::var_os("UL_PATH") {
+        dirs.extend(env::split_paths(&paths));
+    }
+
+    dirs.iter()
+        .map(|dir| dir.join(&name))
+        .find(|candidate| candidate.is_file())
+        .and_then(|found| found.canonicalize().ok())
+        .ok_or_else(|| RuntimeError::Io(format!("{}: module not found", path)))
+}
+
+/// Finds the file `(load path)` refers to: next to the loading file, then
+/// relative to the current directory.
+pub fn locate(path: &str, scope: &Scope) -> Result<PathBuf, RuntimeError> {
+    let file = running_dir(scope)
+        .map(|dir| dir.join(path))
+        .filter(|candidate| candidate.is_file())
+        .unwrap_or_else(|| PathBuf::from(path));
+
+    file.canonicalize()
+        .map_err(|e| RuntimeError::Io(format!("{}: {}", path, e)))
+}
+
+/// Directory of the innermost running file, if any.
+fn running_dir(scope: &Scope) -> Option<PathBuf> {
+    let modules = scope.modules();
+    let running = modules.borrow();
+
+    running.running.last()?.parent().map(Path::to_path_buf)
+}
+
+/// Runs `f` with `file` on the stack of running files, refusing to run a
+/// file that is already running.
+pub fn run_file<T, E>(scope: &Scope, file: &Path, f: impl FnOnce() -> Result<T, E>) -> Result<T, E>
+where
+    E: From<RuntimeError>,
+{
+    let modules = scope.modules();
+
+    if let Some(start) = modules.borrow().running.iter().position(|p| p == file) {
+        let cycle = modules.borrow().running[start..]
+            .iter()
+            .chain([&file.to_path_buf()])
+            .map(|p| p.display().to_string())
+            .collect();
+
+        return Err(RuntimeError::ImportCycle(cycle).into());
+    }
+
+    modules.borrow_mut().running.push(file.to_path_buf());
+    let result = f();
+    modules.borrow_mut().running.pop();
+
+    result
+}
+
+/// Runs the module `path` refers to in a global frame of its own and returns
+/// its exports. Each file runs once, later imports get the cached exports.
+pub fn import(path: &str, scope: &Scope) -> Result<Object, RuntimeError> {
+    let file = resolve(path, scope)?;
+
+    if let Some(exports) = scope.modules().borrow().loaded.get(&file) {
+        return Ok(exports.clone());
+    }
+
+    let source = fs::read_to_string(&file)
+        .map_err(|e| RuntimeError::Io(format!("{}: {}", file.display(), e)))?;
+
+    let module = scope.module();
+
+    run_file(scope, &file, || {
+        let expressions = Parser::with_name(&file.display().to_string(), &source)
+            .parse_expressions()
+            .map_err(RuntimeError::Parse)?;
+
+        for e in expressions {
+            e.execute(&module).map_err(RuntimeError::escaped)?;
+        }
+
+        Ok::<_, RuntimeError>(())
+    })?;
+
+    let exports = module.exports()?;
+
+    scope
+        .modules()
+        .borrow_mut()
+        .loaded
+        .insert(file, exports.clone());
+
+    Ok(exports)
+}
+
+/// Name the exports of `(import path)` are prefixed with when no alias is
+/// given: the file name without its extension.
+pub fn default_prefix(path: &str) -> String {
+    Path::new(path).file_stem().map_or_else(
+        || path.to_string(),
+        |stem| stem.to_string_lossy().into_owned(),
+    )
+}
@@ -1,159 +1,300 @@
-use std::{collections::VecDeque, iter::Peekable, str::Chars};
+use std::{collections::VecDeque, iter::Peekable, rc::Rc, str::CharIndices};
 
 use crate::{
     errors::ParseError,
-    token::{Expression, Identifier, List, Token},
+    source::{SourceFile, Span},
+    token::{Expression, Identifier, List, Number, Object, Token},
+    utils::unescape,
 };
 
+/// Characters identifiers are made of, besides their first one.
+pub fn is_identifier_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || "_&-*?!<>=/+".contains(ch)
+}
+
+/// Whether `text` reads back as an identifier with that name, rather than
+/// as a literal or as several tokens.
+pub fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+
+    let start = match chars.next() {
+        Some('-') => !chars.next().is_some_and(|ch| ch.is_ascii_digit()),
+        Some(ch) => ch.is_ascii_alphabetic() || "_&".contains(ch),
+        None => false,
+    };
+
+    start && text.chars().all(is_identifier_char) && !matches!(text, "true" | "false" | "nil")
+}
+
 pub struct Parser<'a> {
-    chars: Peekable<Chars<'a>>,
-    line: usize,
-    position: usize,
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    /// `None` for parses thrown away once read
+    file: Option<Rc<SourceFile>>,
 }
 
 impl<'a> Parser<'a> {
+    /// Keeps `input` under `name` so errors can point into it.
+    pub fn with_name(name: &str, input: &'a str) -> Self {
+        Self {
+            input,
+            chars: input.char_indices().peekable(),
+            file: Some(SourceFile::new(name, input)),
+        }
+    }
+
+    /// Parses `input` without keeping a copy, for code only checked or
+    /// inspected. Errors don't point into it.
     pub fn new(input: &'a str) -> Self {
         Self {
-            chars: input.trim().chars().peekable(),
-            line: 1,
-            position: 0,
+            input,
+            chars: input.char_indices().peekable(),
+            file: None,
         }
     }
 
     pub fn parse_expressions(&mut self) -> Result<Vec<Expression>, ParseError> {
         let mut expressions = Vec::new();
 
-        loop {
-            let maybe_char = self.chars.peek();
-
-            if maybe_char.is_none() {
-                break;
-            }
-
-            match self.define()? {
-                Some(e) => expressions.push(
-                    e.extract(None)
-                        .map_err(|_| ParseError::ExpectedExpression)?,
-                ),
-                None => (),
+        while self.peek().is_some() {
+            let start = self.offset();
+
+            if let Some(e) = self.define()? {
+                match e {
+                    Token::Expression(e) => expressions.push(e),
+                    _ => {
+                        return Err(ParseError::ExpectedExpression {
+                            span: self.span_from(start),
+                        });
+                    }
+                }
             }
         }
 
         Ok(expressions)
     }
 
-    fn define(&mut self) -> Result<Option<Token>, ParseError> {
-        match self.chars.peek() {
-            Some(' ' | '\t' | '\r') => {
-                self.chars.next();
-                Ok(None)
+    /// Parses the whole input as data, any token may appear at the top level.
+    pub fn parse_forms(&mut self) -> Result<Vec<Token>, ParseError> {
+        let mut forms = Vec::new();
+
+        while self.peek().is_some() {
+            if let Some(token) = self.define()? {
+                forms.push(token);
             }
+        }
+
+        Ok(forms)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, ch)| ch)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        self.chars.next().map(|(_, ch)| ch)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.input.len(), |&(i, _)| i)
+    }
+
+    fn span_from(&mut self, start: usize) -> Span {
+        Span::new(self.file.clone(), start, self.offset())
+    }
 
-            Some('\n') => {
-                self.chars.next();
-                self.line += 1;
-                self.position = 0;
+    fn define(&mut self) -> Result<Option<Token>, ParseError> {
+        match self.peek() {
+            Some(' ' | '\t' | '\r' | '\n') => {
+                self.bump();
                 Ok(None)
             }
 
             Some('#') => {
-                self.chars.find(|&c| c == '\n');
-                self.line += 1;
-                self.position = 0;
+                while self.bump().is_some_and(|c| c != '\n') {}
                 Ok(None)
             }
 
-            Some('(') => self.parse_expression(),
+            Some('(') => self.parse_expression().map(Some),
+
+            Some('\'' | '`' | ',') => self.parse_quote().map(Some),
 
-            Some('[') => self.parse_list(),
+            Some('[') => self.parse_list().map(Some),
 
-            Some('{') => Ok(Some(self.parse_object())),
+            Some('{') => self.parse_object().map(Some),
 
             Some('"') => Ok(Some(self.parse_string()?)),
 
-            Some('-' | '0'..='9') => Ok(Some(self.parse_number())),
+            Some('0'..='9') => self.parse_number().map(Some),
 
-            Some('a'..='z' | 'A'..='Z' | '_') => Ok(Some(self.parse_identifier())),
+            Some('-') if self.next_is_digit() => self.parse_number().map(Some),
+
+            Some('a'..='z' | 'A'..='Z' | '_' | '&' | '-') => Ok(Some(self.parse_identifier())),
 
             None => Ok(None),
 
-            ch => {
-                return Err(ParseError::UnknownToken {
-                    line: self.line,
-                    position: self.position,
-                    ch: *ch.unwrap(),
-                });
+            Some(ch) => {
+                let start = self.offset();
+                self.bump();
+
+                Err(ParseError::UnknownToken {
+                    span: self.span_from(start),
+                    ch,
+                })
             }
         }
     }
 
-    fn parse_expression(&mut self) -> Result<Option<Token>, ParseError> {
-        let mut tokens = VecDeque::new();
+    /// Parses the elements up to `close`, keeping the span of each of them.
+    /// Returns `None` when the input ends before the closing bracket.
+    fn parse_sequence(&mut self, close: char) -> Result<Option<Vec<(Token, Span)>>, ParseError> {
+        self.bump();
 
-        self.chars.next();
+        let mut items = Vec::new();
 
-        while let Some(&ch) = self.chars.peek() {
-            let maybe_token = match ch {
-                ')' => {
-                    self.chars.next();
-                    return Ok(Some(Token::Expression(Expression::from_iterable(
-                        tokens,
-                        self.line,
-                        self.position,
-                    ))));
-                }
-                _ => self.define()?,
-            };
+        while let Some(ch) = self.peek() {
+            if ch == close {
+                self.bump();
+                return Ok(Some(items));
+            }
 
-            self.position += 1;
+            let start = self.offset();
 
-            if maybe_token.is_some() {
-                tokens.push_back(maybe_token.unwrap());
+            if let Some(token) = self.define()? {
+                items.push((token, self.span_from(start)));
             }
         }
 
-        if tokens.len() != 0 {
+        Ok(None)
+    }
+
+    fn parse_expression(&mut self) -> Result<Token, ParseError> {
+        let start = self.offset();
+
+        let Some(items) = self.parse_sequence(')')? else {
             return Err(ParseError::IncompleteExpression {
-                line: self.line,
-                position: self.position,
+                span: self.span_from(start),
             });
-        }
+        };
 
-        Ok(None)
+        let (tokens, spans): (VecDeque<_>, Vec<_>) = items.into_iter().unzip();
+
+        let mut expression = Expression::from_iterable(tokens, self.span_from(start));
+        expression.spans = spans;
+
+        Ok(Token::Expression(expression))
     }
 
-    fn parse_number(&mut self) -> Token {
-        let mut num_str = String::new();
+    /// `'x`, `` `x ``, `,x` and `,@x` read as `(quote x)`, `(quasiquote x)`,
+    /// `(unquote x)` and `(unquote-splicing x)`.
+    fn parse_quote(&mut self) -> Result<Token, ParseError> {
+        let start = self.offset();
+
+        let form = match self.bump() {
+            Some('\'') => "quote",
+            Some('`') => "quasiquote",
+            _ if self.peek() == Some('@') => {
+                self.bump();
+                "unquote-splicing"
+            }
+            _ => "unquote",
+        };
+
+        let head = self.span_from(start);
+
+        let quoted = loop {
+            let item_start = self.offset();
+
+            match self.define()? {
+                Some(token) => break (token, self.span_from(item_start)),
+                None if self.peek().is_none() => {
+                    return Err(ParseError::ExpectedExpression {
+                        span: self.span_from(start),
+                    });
+                }
+                None => (),
+            }
+        };
+
+        let mut expression = Expression::from_iterable(
+            [Token::Identifier(Identifier(form.to_string())), quoted.0],
+            self.span_from(start),
+        );
+        expression.spans = vec![head, quoted.1];
+
+        Ok(Token::Expression(expression))
+    }
+
+    /// Whether the character after the current one is a digit, to tell `-1`
+    /// from an identifier starting with `-`.
+    fn next_is_digit(&mut self) -> bool {
+        let offset = self.offset();
+
+        self.input[offset..]
+            .chars()
+            .nth(1)
+            .is_some_and(|ch| ch.is_ascii_digit())
+    }
+
+    /// Reads everything that may belong to a number, so `1.2.3` or `12abc`
+    /// are reported whole instead of being split into several tokens.
+    fn parse_number(&mut self) -> Result<Token, ParseError> {
+        let start = self.offset();
+        let mut text = String::new();
+
+        if self.peek() == Some('-') {
+            text.push(self.bump().unwrap());
+        }
 
         loop {
-            match self.chars.peek() {
-                Some('0'..='9' | '.') => {
-                    self.position += 1;
-                    num_str.push(self.chars.next().unwrap());
+            let exponent_sign = matches!(text.chars().last(), Some('e' | 'E'))
+                && !text.to_ascii_lowercase().contains("0x");
+
+            match self.peek() {
+                Some('0'..='9' | 'a'..='z' | 'A'..='Z' | '_' | '.') => {
+                    text.push(self.bump().unwrap())
                 }
-                _ => return Token::Number(num_str.parse().unwrap()),
+                Some('+' | '-') if exponent_sign => text.push(self.bump().unwrap()),
+                _ => break,
             }
         }
+
+        match Number::parse(&text) {
+            Some(number) => Ok(Token::Number(number)),
+            None => Err(ParseError::InvalidNumber {
+                span: self.span_from(start),
+                text,
+            }),
+        }
     }
 
+    /// Escape sequences are resolved here, the string holds the characters
+    /// they stand for.
     fn parse_string(&mut self) -> Result<Token, ParseError> {
+        let start = self.offset();
         let mut string = String::new();
-        self.chars.next();
+        self.bump();
 
         loop {
-            self.position += 1;
-            match self.chars.peek() {
+            match self.peek() {
                 Some('"') => {
-                    self.chars.next();
-                    return Ok(Token::String(string));
+                    self.bump();
+                    return Ok(Token::String(unescape(&string)));
                 }
                 None | Some('\n') => {
                     return Err(ParseError::UnterminatedString {
-                        line: self.line,
-                        position: self.position,
+                        span: self.span_from(start),
                     });
                 }
-                Some(_) => string.push(self.chars.next().unwrap()),
+                // Keep the escaped character, it may be a quote
+                Some('\\') => {
+                    string.push(self.bump().unwrap());
+
+                    if let Some(ch) = self.bump() {
+                        string.push(ch);
+                    }
+                }
+                Some(_) => string.push(self.bump().unwrap()),
             }
         }
     }
@@ -162,11 +303,8 @@ impl<'a> Parser<'a> {
         let mut id = String::new();
 
         loop {
-            match self.chars.peek() {
-                Some('a'..='z' | 'A'..='Z' | '_' | '0'..='9') => {
-                    self.position += 1;
-                    id.push(self.chars.next().unwrap())
-                }
+            match self.peek() {
+                Some(ch) if is_identifier_char(ch) => id.push(self.bump().unwrap()),
                 _ => {
                     let token = match id.as_str() {
                         "true" => Token::Bool(true),
@@ -181,41 +319,55 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_list(&mut self) -> Result<Option<Token>, ParseError> {
-        self.chars.next();
-
-        let mut list = VecDeque::new();
+    fn parse_list(&mut self) -> Result<Token, ParseError> {
+        let start = self.offset();
 
-        while let Some(&ch) = self.chars.peek() {
-            let token = match ch {
-                ']' => {
-                    self.chars.next();
-                    return Ok(Some(Token::List(List::from_iterable(list))));
-                }
-                _ => self.define()?,
-            };
+        let Some(items) = self.parse_sequence(']')? else {
+            return Err(ParseError::IncompleteList {
+                span: self.span_from(start),
+            });
+        };
 
-            self.position += 1;
+        Ok(Token::List(List::with_spans(items)))
+    }
 
-            if token.is_some() {
-                list.push_back(token.unwrap());
-            }
-        }
+    /// Keys of `{key value ...}` are bare identifiers, strings, numbers or
+    /// bools and are stored by their textual form.
+    fn parse_object(&mut self) -> Result<Token, ParseError> {
+        let start = self.offset();
 
-        if list.len() != 0 {
-            return Err(ParseError::IncompleteList {
-                line: self.line,
-                position: self.position,
+        let Some(items) = self.parse_sequence('}')? else {
+            return Err(ParseError::IncompleteObject {
+                span: self.span_from(start),
             });
-        }
+        };
 
-        Ok(None)
-    }
+        let mut object = Object::default();
+        let mut items = items.into_iter();
 
-    fn parse_object(&mut self) -> Token {
-        self.chars.next();
+        while let Some((key, key_span)) = items.next() {
+            let key = match key {
+                Token::Identifier(id) => id.0,
+                Token::String(_) | Token::Number(_) | Token::Bool(_) => key.to_string(),
+                _ => {
+                    return Err(ParseError::InvalidObjectKey {
+                        span: key_span,
+                        found: key.as_type(),
+                    });
+                }
+            };
 
-        todo!()
+            let Some((value, value_span)) = items.next() else {
+                return Err(ParseError::MissingObjectValue {
+                    span: key_span,
+                    key,
+                });
+            };
+
+            object.insert_spanned(key, value, value_span);
+        }
+
+        Ok(Token::Object(object))
     }
 }
 
@@ -225,45 +377,252 @@ mod tests {
 
     #[test]
     fn number() {
-        let mut parser = Parser::new("123.456");
+        let mut parser = Parser::with_name("<test>", "123.456");
+
+        let result = Token::Number(Number::Float(123.456));
 
-        let result = Token::Number(123.456);
+        assert_eq!(Ok(result), parser.parse_number());
 
-        assert_eq!(result, parser.parse_number());
+        let mut parser = Parser::with_name("<test>", "(f -1_000 0xff 2e3 -x)");
+        let expressions = parser.parse_expressions().unwrap();
+
+        assert_eq!(
+            expressions[0].args,
+            List::from_iterable([
+                Token::Number(Number::Int(-1000)),
+                Token::Number(Number::Int(255)),
+                Token::Number(Number::Float(2000.0)),
+                Token::Identifier(Identifier("-x".to_string())),
+            ])
+        );
+
+        let mut parser = Parser::with_name("<test>", "(add 1.2.3 1)");
+
+        assert!(matches!(
+            parser.parse_expressions(),
+            Err(ParseError::InvalidNumber { text, .. }) if text == "1.2.3"
+        ));
     }
 
     #[test]
     fn string() {
-        let mut parser = Parser::new("\"Hello\"");
+        let mut parser = Parser::with_name("<test>", "\"Hello\"");
 
         let result = Token::String("Hello".to_string());
 
         assert_eq!(result, parser.parse_string().unwrap());
     }
 
+    #[test]
+    fn string_escapes() {
+        let mut parser = Parser::with_name("<test>", r#""say \"hi\"\n\t\\ \q""#);
+
+        let result = Token::String("say \"hi\"\n\t\\ q".to_string());
+
+        assert_eq!(result, parser.parse_string().unwrap());
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = r#"[1 -2.5 "a \"b\"\n" true nil [x []] {key "v" "two words" 1 "3" {}}]
+            (add 1 (mul x 2) "s") '(quote y) 99999999999999999999"#;
+
+        let printed: Vec<String> = Parser::with_name("<test>", source)
+            .parse_forms()
+            .unwrap()
+            .iter()
+            .map(Token::repr)
+            .collect();
+
+        assert_eq!(
+            printed,
+            [
+                r#"[1 -2.5 "a \"b\"\n" true nil [x []] {"3" {} key "v" "two words" 1}]"#,
+                r#"(add 1 (mul x 2) "s")"#,
+                "(quote (quote y))",
+                "99999999999999999999",
+            ]
+        );
+
+        let reread = Parser::with_name("<test>", &printed.join(" "))
+            .parse_forms()
+            .unwrap();
+
+        assert_eq!(reread.iter().map(Token::repr).collect::<Vec<_>>(), printed);
+        assert_eq!(
+            reread[0],
+            Parser::with_name("<test>", source).parse_forms().unwrap()[0]
+        );
+    }
+
     #[test]
     fn bool() {
-        let mut parser = Parser::new("true");
+        let mut parser = Parser::with_name("<test>", "true");
 
         let result = Token::Bool(true);
 
         assert_eq!(result, parser.parse_identifier());
     }
 
+    #[test]
+    fn identifier() {
+        let mut parser = Parser::with_name("<test>", "let* str->number empty? ");
+
+        let result = ["let*", "str->number", "empty?"]
+            .map(|id| Some(Token::Identifier(Identifier(id.to_string()))));
+
+        for expected in result {
+            assert_eq!(expected, parser.define().unwrap());
+            parser.define().unwrap();
+        }
+    }
+
+    #[test]
+    fn quote() {
+        let mut parser = Parser::with_name("<test>", "'(a `[b ,c ,@d])");
+
+        let Some(Token::Expression(quote)) = parser.define().unwrap() else {
+            panic!("expected an expression");
+        };
+
+        assert_eq!(quote.fid, Some(Identifier("quote".to_string())));
+        assert_eq!(quote.spans.len(), 2);
+
+        let Token::Expression(quoted) = &quote.args[0] else {
+            panic!("expected an expression");
+        };
+
+        let Token::Expression(quasiquote) = &quoted.args[0] else {
+            panic!("expected an expression");
+        };
+
+        let Token::List(items) = &quasiquote.args[0] else {
+            panic!("expected a list");
+        };
+
+        let heads: Vec<_> = items
+            .iter()
+            .skip(1)
+            .map(|item| match item {
+                Token::Expression(e) => e.fid.as_ref().unwrap().0.as_str(),
+                _ => "",
+            })
+            .collect();
+
+        assert_eq!(heads, ["unquote", "unquote-splicing"]);
+    }
+
     #[test]
     fn list() {
-        let mut parser = Parser::new("[1 2 3 4 5 \"Hello\"]");
+        let mut parser = Parser::with_name("<test>", "[1 2 3 4 5 \"Hello\"]");
 
         let result = Token::List(List::from_iterable([
-            Token::Number(1.0),
-            Token::Number(2.0),
-            Token::Number(3.0),
-            Token::Number(4.0),
-            Token::Number(5.0),
+            Token::Number(Number::Int(1)),
+            Token::Number(Number::Int(2)),
+            Token::Number(Number::Int(3)),
+            Token::Number(Number::Int(4)),
+            Token::Number(Number::Int(5)),
             Token::String("Hello".to_string()),
         ]));
 
-        assert_eq!(result, parser.parse_list().unwrap().unwrap());
+        assert_eq!(result, parser.parse_list().unwrap());
+    }
+
+    #[test]
+    fn object() {
+        let mut parser = Parser::with_name("<test>", "{name \"Denis\" \"age\" 20 1 true}");
+
+        let result = Token::Object(Object::from_iterable([
+            ("name".to_string(), Token::String("Denis".to_string())),
+            ("age".to_string(), Token::Number(Number::Int(20))),
+            ("1".to_string(), Token::Bool(true)),
+        ]));
+
+        assert_eq!(result, parser.parse_object().unwrap());
+    }
+
+    #[test]
+    fn item_spans() {
+        let source = "[1 (add 2 3)] {a \"b\" c [4]}";
+        let forms = Parser::with_name("<test>", source).parse_forms().unwrap();
+        let text = |span: Option<&Span>| &source[span.unwrap().start..span.unwrap().end];
+
+        let Token::List(list) = &forms[0] else {
+            panic!("expected a list");
+        };
+        assert_eq!([text(list.span(0)), text(list.span(1))], ["1", "(add 2 3)"]);
+
+        let Token::Object(object) = &forms[1] else {
+            panic!("expected an object");
+        };
+        assert_eq!(
+            [text(object.span("a")), text(object.span("c"))],
+            ["\"b\"", "[4]"]
+        );
+    }
+
+    #[test]
+    fn object_missing_value() {
+        let mut parser = Parser::with_name("<test>", "{name}");
+
+        assert!(matches!(
+            parser.parse_object(),
+            Err(ParseError::MissingObjectValue { .. })
+        ));
+    }
+
+    #[test]
+    fn spans() {
+        let source = "(add 1\n  (mul 2 3))";
+        let expressions = Parser::with_name("<test>", source)
+            .parse_expressions()
+            .unwrap();
+
+        let add = &expressions[0];
+        assert_eq!((add.span.start, add.span.end), (0, source.len()));
+
+        let ranges: Vec<_> = add.spans.iter().map(|s| &source[s.start..s.end]).collect();
+        assert_eq!(ranges, ["add", "1", "(mul 2 3)"]);
+    }
+
+    #[test]
+    fn error_span() {
+        let error = Parser::with_name("<test>", "(print 1)\n(print \"oops)")
+            .parse_expressions()
+            .unwrap_err();
+
+        assert_eq!(crate::source::line_col(error.span()), Some((2, 8)));
+    }
+
+    #[test]
+    fn sources_live_with_their_spans() {
+        let expressions = Parser::with_name("<test>", "(add 1 2)")
+            .parse_expressions()
+            .unwrap();
+
+        let file = Rc::downgrade(expressions[0].span.file.as_ref().unwrap());
+        drop(expressions);
+        assert!(file.upgrade().is_none());
+
+        let error = Parser::new("(add 1").parse_expressions().unwrap_err();
+        assert_eq!(crate::source::line_col(error.span()), None);
+    }
+
+    #[test]
+    fn incomplete() {
+        for source in ["(func f [x]\n", "(print [1 2", "(do {a 1"] {
+            let error = Parser::with_name("<test>", source)
+                .parse_expressions()
+                .unwrap_err();
+
+            assert!(error.is_incomplete(), "{}", source);
+        }
+
+        let error = Parser::with_name("<test>", "(print \"oops")
+            .parse_expressions()
+            .unwrap_err();
+
+        assert!(!error.is_incomplete());
     }
 
     // #[test]
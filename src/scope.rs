@@ -1,82 +1,642 @@
 use std::{
+    cell::{Cell, RefCell},
     collections::HashMap,
-    hash::{DefaultHasher, Hash, Hasher},
-    sync::RwLock,
+    fmt::{Debug, Display},
+    rc::{Rc, Weak},
 };
 
-use once_cell::sync::Lazy;
-
 use crate::{
-    errors::RuntimeError,
-    token::{Expression, Identifier, List, Token},
+    builtins::builtin_functions,
+    errors::{CallFrame, RuntimeError},
+    host::Builtin,
+    modules::Modules,
+    stdlib::math,
+    token::{Expression, Identifier, List, Object, Tail, Token, run},
 };
 
-pub static VARIABLES: Lazy<RwLock<HashMap<String, Token>>> =
-    Lazy::new(|| RwLock::new(HashMap::new()));
+/// Builtin functions of one interpreter, shared by all of its frames
+type Builtins = Rc<RefCell<HashMap<String, Builtin>>>;
+
+#[derive(Default)]
+struct Frame {
+    variables: HashMap<String, Token>,
+    functions: HashMap<String, Function>,
+    macros: HashMap<String, Function>,
+    builtins: Builtins,
+    modules: Rc<RefCell<Modules>>,
+    /// Identifiers made by `gensym` so far, shared like the builtins
+    symbols: Rc<Cell<usize>>,
+    /// Names declared with `export`, only used in global frames
+    exports: Vec<String>,
+    parent: Option<Scope>,
+}
+
+/// Handle to a lexical environment frame. Cloning the handle shares the frame,
+/// lookups walk the chain of parents until the global frame is reached.
+#[derive(Clone)]
+pub struct Scope(Rc<RefCell<Frame>>);
+
+impl Scope {
+    /// Creates a global frame with its own set of the standard builtins.
+    pub fn new() -> Self {
+        Self(Rc::new(RefCell::new(Frame {
+            builtins: Rc::new(RefCell::new(builtin_functions())),
+            ..Default::default()
+        })))
+    }
+
+    pub fn child(&self) -> Self {
+        Self(Rc::new(RefCell::new(Frame {
+            builtins: self.0.borrow().builtins.clone(),
+            modules: self.0.borrow().modules.clone(),
+            symbols: self.0.borrow().symbols.clone(),
+            parent: Some(self.clone()),
+            ..Default::default()
+        })))
+    }
+
+    /// Creates the global frame of a module, it shares the builtins and the
+    /// loaded modules with this interpreter but none of its names.
+    pub fn module(&self) -> Self {
+        Self(Rc::new(RefCell::new(Frame {
+            builtins: self.0.borrow().builtins.clone(),
+            modules: self.0.borrow().modules.clone(),
+            symbols: self.0.borrow().symbols.clone(),
+            ..Default::default()
+        })))
+    }
+
+    pub(crate) fn modules(&self) -> Rc<RefCell<Modules>> {
+        self.0.borrow().modules.clone()
+    }
+
+    /// Numbers the identifiers `gensym` makes, counting per interpreter.
+    pub(crate) fn next_symbol(&self) -> usize {
+        let frame = self.0.borrow();
+        let n = frame.symbols.get();
+        frame.symbols.set(n + 1);
+        n
+    }
+
+    /// Marks `name` as exported from the module this frame belongs to.
+    pub fn export(&self, name: &str) {
+        let parent = self.0.borrow().parent.clone();
+
+        match parent {
+            Some(parent) => parent.export(name),
+            None => self.0.borrow_mut().exports.push(name.to_string()),
+        }
+    }
+
+    /// Values this global frame exports: the names declared with `export`, or
+    /// every variable and function when there are none.
+    pub fn exports(&self) -> Result<Object, RuntimeError> {
+        let frame = self.0.borrow();
+        let mut exports = Object::default();
+
+        if frame.exports.is_empty() {
+            for (name, function) in &frame.functions {
+                exports.insert(name.clone(), Token::Function(function.attached()));
+            }
+
+            for (name, value) in &frame.variables {
+                exports.insert(name.clone(), attached(value));
+            }
+
+            return Ok(exports);
+        }
+
+        for name in &frame.exports {
+            if !frame.variables.contains_key(name) && !frame.functions.contains_key(name) {
+                return Err(RuntimeError::InvalidValue(format!(
+                    "exported name `{}` is not defined",
+                    name
+                )));
+            }
+
+            exports.insert(name.clone(), self.get_variable(name));
+        }
+
+        Ok(exports)
+    }
+
+    /// Variables bound in this frame, sorted by name.
+    pub fn variables(&self) -> Vec<(String, Token)> {
+        let mut variables: Vec<_> = self
+            .0
+            .borrow()
+            .variables
+            .iter()
+            .map(|(name, value)| (name.clone(), attached(value)))
+            .collect();
+
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
+
+    /// Functions declared with `func` in this frame, sorted by name.
+    pub fn functions(&self) -> Vec<Function> {
+        sorted(self.0.borrow().functions.values())
+    }
+
+    /// Macros declared with `defmacro` in this frame, sorted by name.
+    pub fn macros(&self) -> Vec<Function> {
+        sorted(self.0.borrow().macros.values())
+    }
+
+    pub fn get_builtin(&self, name: &str) -> Option<Builtin> {
+        self.0.borrow().builtins.borrow().get(name).cloned()
+    }
+
+    /// Names of the builtins of the interpreter, sorted.
+    pub fn builtin_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.0.borrow().builtins.borrow().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Adds or replaces a builtin, every frame of the interpreter sees it.
+    pub fn set_builtin(&self, name: &str, builtin: Builtin) {
+        self.0
+            .borrow()
+            .builtins
+            .borrow_mut()
+            .insert(name.to_string(), builtin);
+    }
+
+    pub fn has_variable(&self, name: &str) -> bool {
+        let frame = self.0.borrow();
+
+        frame.variables.contains_key(name)
+            || match &frame.parent {
+                Some(parent) => parent.has_variable(name),
+                None => math::constant(name).is_some(),
+            }
+    }
+
+    /// Resolves `name` as a value. Named functions declared with `func` are
+    /// visible as function values when no variable of that name is in scope,
+    /// and so are builtins when nothing else binds their name.
+    pub fn get_variable(&self, name: &str) -> Token {
+        let frame = self.0.borrow();
+
+        if let Some(v) = frame.variables.get(name) {
+            return attached(v);
+        }
+
+        if let Some(f) = frame.functions.get(name) {
+            return Token::Function(f.attached());
+        }
+
+        match &frame.parent {
+            Some(parent) => parent.get_variable(name),
+            None => math::constant(name)
+                .or_else(|| {
+                    let builtin = frame.builtins.borrow().get(name).cloned()?;
+                    let function = Function::native(name, builtin, self.clone());
+                    Some(Token::Function(function))
+                })
+                .unwrap_or(Token::Nil),
+        }
+    }
+
+    /// Binds `name` in this frame, shadowing any binding of the same name in
+    /// the enclosing frames.
+    pub fn set_variable(&self, name: &str, value: Token) {
+        let value = self.detached(value);
+
+        self.0
+            .borrow_mut()
+            .variables
+            .insert(name.to_string(), value);
+    }
+
+    /// Rebinds `name` in the nearest frame that binds it, which may be an
+    /// enclosing one. Unlike [`Scope::set_variable`] it never creates a
+    /// binding.
+    pub fn assign(&self, name: &str, value: Token) -> Result<(), RuntimeError> {
+        let value = self.detached(value);
+        let mut frame = self.0.borrow_mut();
+
+        if frame.variables.contains_key(name) || frame.functions.remove(name).is_some() {
+            frame.variables.insert(name.to_string(), value);
+            return Ok(());
+        }
+
+        match &frame.parent {
+            Some(parent) => parent.assign(name, value),
+            None => Err(RuntimeError::UndefinedVariable(name.to_string())),
+        }
+    }
+
+    /// Resolves the callee of `(name ...)`: the nearest frame that declares
+    /// either a function or a variable holding a function value wins.
+    pub fn get_function(&self, name: &str) -> Option<Function> {
+        let frame = self.0.borrow();
+
+        if let Some(f) = frame.functions.get(name) {
+            return Some(f.attached());
+        }
+
+        if let Some(Token::Function(f)) = frame.variables.get(name) {
+            return Some(f.attached());
+        }
+
+        frame.parent.as_ref().and_then(|p| p.get_function(name))
+    }
+
+    pub fn set_function(
+        &self,
+        name: &str,
+        arg_names: List,
+        body: Expression,
+    ) -> Result<(), RuntimeError> {
+        let mut function = self.detached_function(Function::new(body, arg_names, self.clone())?);
+        function.name = Some(name.to_string());
+
+        self.0
+            .borrow_mut()
+            .functions
+            .insert(name.to_string(), function);
+
+        Ok(())
+    }
+
+    pub fn get_macro(&self, name: &str) -> Option<Function> {
+        let frame = self.0.borrow();
+
+        if let Some(m) = frame.macros.get(name) {
+            return Some(m.attached());
+        }
+
+        frame.parent.as_ref().and_then(|p| p.get_macro(name))
+    }
+
+    /// Declares a macro, a function called with the unevaluated argument
+    /// forms whose result is evaluated in place of the call.
+    pub fn set_macro(
+        &self,
+        name: &str,
+        arg_names: List,
+        body: Expression,
+    ) -> Result<(), RuntimeError> {
+        let mut function = self.detached_function(Function::new(body, arg_names, self.clone())?);
+        function.name = Some(name.to_string());
+
+        self.0
+            .borrow_mut()
+            .macros
+            .insert(name.to_string(), function);
+
+        Ok(())
+    }
+}
+
+impl Scope {
+    /// A function stored in the frame it was defined in refers back to that
+    /// frame weakly, or the frame would own itself and never be freed.
+    fn detached_function(&self, mut function: Function) -> Function {
+        if let Closure::Strong(scope) = &function.scope
+            && scope == self
+        {
+            function.scope = Closure::Weak(Rc::downgrade(&self.0));
+        }
+
+        function
+    }
+
+    fn detached(&self, value: Token) -> Token {
+        match value {
+            Token::Function(function) => Token::Function(self.detached_function(function)),
+            value => value,
+        }
+    }
+}
+
+/// A copy of a value stored in a frame that holds on to the frame of a
+/// function, see [`Function::attached`].
+fn attached(value: &Token) -> Token {
+    match value {
+        Token::Function(function) => Token::Function(function.attached()),
+        value => value.clone(),
+    }
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for Scope {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Debug for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Scope")
+    }
+}
+
+fn sorted<'a>(functions: impl Iterator<Item = &'a Function>) -> Vec<Function> {
+    let mut functions: Vec<Function> = functions.map(Function::attached).collect();
+    functions.sort_by(|a, b| a.name().cmp(b.name()));
+    functions
+}
+
+/// How a function refers to the frame it was defined in.
+#[derive(Clone)]
+enum Closure {
+    Strong(Scope),
+    /// Held by functions stored in their own defining frame
+    Weak(Weak<RefCell<Frame>>),
+}
+
+impl Closure {
+    fn scope(&self) -> Scope {
+        match self {
+            Self::Strong(scope) => scope.clone(),
+            Self::Weak(frame) => Scope(
+                frame
+                    .upgrade()
+                    .expect("functions are attached before they leave their frame"),
+            ),
+        }
+    }
+}
+
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        let pointer = |closure: &Self| match closure {
+            Self::Strong(scope) => Rc::as_ptr(&scope.0),
+            Self::Weak(frame) => frame.as_ptr(),
+        };
+
+        pointer(self) == pointer(other)
+    }
+}
 
-pub static FUNCTIONS: Lazy<RwLock<HashMap<String, Function>>> =
-    Lazy::new(|| RwLock::new(HashMap::new()));
+impl Debug for Closure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Scope")
+    }
+}
+
+/// What a function runs when called.
+#[derive(Debug, Clone, PartialEq)]
+enum Body {
+    Code(Rc<Expression>),
+    /// A builtin taken as a value, see [`Scope::get_variable`]
+    Native(Builtin),
+}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Function {
+    /// Set for functions declared with `func` and for builtins, lambdas are
+    /// anonymous
+    name: Option<String>,
     arg_names: Vec<String>,
-    body: Expression,
+    rest: Option<String>,
+    body: Body,
+    scope: Closure,
 }
 
 impl Function {
-    pub fn new(body: Expression, arg_names: List) -> Result<Self, RuntimeError> {
-        let arg_names: Vec<String> = {
-            let mut v = vec![];
+    /// Parses the parameter list, `[a b & rest]` collects the remaining
+    /// arguments into a list bound to `rest`.
+    pub fn new(body: Expression, arg_names: List, scope: Scope) -> Result<Self, RuntimeError> {
+        let mut names = vec![];
 
-            for token in arg_names.0 {
-                v.push(token.extract::<Identifier>(None)?.0);
+        for (token, span) in arg_names.into_spanned() {
+            let name: Identifier = token.extract(&scope).map_err(|e| e.located_at(span))?;
+            names.push(name.0);
+        }
+
+        let rest = match names.iter().position(|n| n == "&") {
+            Some(i) if i + 2 == names.len() => {
+                let rest = names.pop();
+                names.pop();
+                rest
+            }
+            Some(_) => {
+                return Err(RuntimeError::InvalidValue(
+                    "`&` must be followed by exactly one parameter".to_string(),
+                ));
             }
+            None => None,
+        };
+
+        Ok(Self {
+            name: None,
+            arg_names: names,
+            rest,
+            body: Body::Code(Rc::new(body)),
+            scope: Closure::Strong(scope),
+        })
+    }
+
+    /// Wraps the builtin `name`, it runs in `scope` when called.
+    pub fn native(name: &str, builtin: Builtin, scope: Scope) -> Self {
+        Self {
+            name: Some(name.to_string()),
+            arg_names: Vec::new(),
+            rest: None,
+            body: Body::Native(builtin),
+            scope: Closure::Strong(scope),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("<lambda>")
+    }
+
+    pub fn arg_names(&self) -> &[String] {
+        &self.arg_names
+    }
+
+    /// Name of the parameter collecting the extra arguments, if any
+    pub fn rest(&self) -> Option<&str> {
+        self.rest.as_deref()
+    }
 
-            v
+    /// A string written first in a body of several forms documents the
+    /// function: `(func double [x] "Doubles x." (mul x 2))`.
+    pub fn doc(&self) -> Option<&str> {
+        let body = match &self.body {
+            Body::Code(body) => body,
+            Body::Native(builtin) => return builtin.doc(),
         };
 
-        Ok(Self { arg_names, body })
+        match (&body.fid, body.args.front()) {
+            (Some(id), Some(Token::String(doc))) if id.0 == "do" && body.args.len() > 1 => {
+                Some(doc)
+            }
+            _ => None,
+        }
     }
 
-    pub fn call(self, name: String, args: List) -> Result<Token, RuntimeError> {
-        let prefix = format!("${}_", name);
+    /// `None` for builtins taken as values.
+    pub fn body(&self) -> Option<&Rc<Expression>> {
+        match &self.body {
+            Body::Code(body) => Some(body),
+            Body::Native(_) => None,
+        }
+    }
 
-        self.arg_names
-            .iter()
-            .zip(args.0.into_iter())
-            .for_each(|(name, value)| {
-                set_variable(&(prefix.clone() + &name), value);
-            });
+    pub fn builtin(&self) -> Option<&Builtin> {
+        match &self.body {
+            Body::Native(builtin) => Some(builtin),
+            Body::Code(_) => None,
+        }
+    }
 
-        self.body.execute(Some(prefix))
+    pub fn scope(&self) -> Scope {
+        self.scope.scope()
     }
-}
 
-pub fn get_variable(name: &String) -> Token {
-    let variables = VARIABLES.read().unwrap();
+    /// A copy that keeps the frame the function was defined in alive, for
+    /// functions taken out of that frame.
+    fn attached(&self) -> Function {
+        Function {
+            scope: Closure::Strong(self.scope()),
+            ..self.clone()
+        }
+    }
 
-    match variables.get(name) {
-        Some(v) => v.clone(),
-        None => Token::Nil,
+    /// Applies the function to already evaluated `args`.
+    pub fn apply(self, args: Vec<Token>) -> Result<Token, RuntimeError> {
+        run(Tail::Call(self, args), None)
     }
-}
 
-pub fn set_variable(name: &String, value: Token) {
-    let mut variables = VARIABLES.write().unwrap();
+    /// Checks the number of `args`, packing the extra ones into a list when
+    /// the function takes a rest parameter.
+    pub fn bind_args(&self, mut args: Vec<Token>) -> Result<Vec<Token>, RuntimeError> {
+        let count = self.arg_names.len();
+
+        match self.rest {
+            Some(_) if args.len() < count => Err(RuntimeError::NotEnoughArgs { min: count }),
+            Some(_) => {
+                let rest = args.split_off(count);
+                args.push(Token::List(List::from_iterable(rest)));
+                Ok(args)
+            }
+            None if args.len() != count => Err(RuntimeError::InvalidArgCount {
+                expected: count,
+                got: args.len(),
+            }),
+            None => Ok(args),
+        }
+    }
+
+    /// Names the values returned by [`Function::bind_args`] are bound to.
+    pub fn params(&self) -> impl Iterator<Item = &String> {
+        self.arg_names.iter().chain(&self.rest)
+    }
+
+    /// Binds `args` in a fresh frame whose parent is the scope the function
+    /// was defined in, returning the body to run there. Builtins have no body
+    /// and are called by [`run`] directly.
+    pub fn enter(self, args: Vec<Token>) -> Result<(Expression, Scope, CallFrame), RuntimeError> {
+        if let Body::Native(_) = self.body {
+            panic!("builtin `{}` has no body to enter", self.name());
+        }
+
+        let args = self.bind_args(args)?;
+        let frame = self.scope().child();
+
+        for (name, value) in self.params().zip(args.iter()) {
+            frame.set_variable(name, value.clone());
+        }
+
+        let call_frame = CallFrame {
+            function: self.name().to_string(),
+            args: self.params().cloned().zip(args).collect(),
+            call_site: None,
+        };
+
+        let Body::Code(body) = self.body else {
+            unreachable!()
+        };
+
+        Ok((Rc::unwrap_or_clone(body), frame, call_frame))
+    }
+}
 
-    variables.insert(name.clone(), value);
+impl PartialOrd for Function {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        (self == other).then_some(std::cmp::Ordering::Equal)
+    }
 }
 
-pub fn get_function(name: &String) -> Option<Function> {
-    let functions = FUNCTIONS.read().unwrap();
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Body::Native(_) = self.body {
+            return write!(f, "<builtin {}>", self.name());
+        }
+
+        let mut params: Vec<&str> = self.arg_names.iter().map(String::as_str).collect();
+
+        if let Some(rest) = &self.rest {
+            params.extend(["&", rest]);
+        }
 
-    functions.get(name).cloned()
+        write!(f, "<function [{}]>", params.join(" "))
+    }
 }
 
-pub fn set_function(name: &String, arg_names: List, body: Expression) -> Result<(), RuntimeError> {
-    let mut functions = FUNCTIONS.write().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Number;
+
+    #[test]
+    fn shadowing() {
+        let global = Scope::new();
+        global.set_variable("x", Token::Number(Number::Int(1)));
 
-    functions.insert(name.clone(), Function::new(body, arg_names)?);
+        let local = global.child();
+        assert_eq!(local.get_variable("x"), Token::Number(Number::Int(1)));
 
-    Ok(())
+        local.set_variable("x", Token::Number(Number::Int(2)));
+        assert_eq!(local.get_variable("x"), Token::Number(Number::Int(2)));
+        assert_eq!(global.get_variable("x"), Token::Number(Number::Int(1)));
+    }
+
+    #[test]
+    fn frames_defining_functions_are_freed() {
+        let global = Scope::new();
+        let frame = global.child();
+        let body = Expression::from_iterable([Token::Nil], Default::default());
+
+        frame
+            .set_function("f", List::from_iterable([]), body.clone())
+            .unwrap();
+        frame.set_variable(
+            "g",
+            Token::Function(Function::new(body, List::from_iterable([]), frame.clone()).unwrap()),
+        );
+
+        let f = frame.get_function("f").unwrap();
+        assert_eq!(f.scope(), frame);
+
+        let weak = Rc::downgrade(&frame.0);
+        drop(frame);
+        assert!(
+            weak.upgrade().is_some(),
+            "a function taken out keeps its frame"
+        );
+
+        drop(f);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn undefined_is_nil() {
+        let scope = Scope::new().child();
+
+        assert!(!scope.has_variable("missing"));
+        assert_eq!(scope.get_variable("missing"), Token::Nil);
+    }
 }
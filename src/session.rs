@@ -0,0 +1,453 @@
+use std::{collections::HashMap, error::Error, fs, path::PathBuf, time::Instant};
+
+use uncommon_lisp::{
+    Interpreter,
+    parser::Parser,
+    scope::Function,
+    token::{Expression, Token},
+};
+
+const HELP: &str = "\
+:help           show this help
+:env            list the variables, functions and macros defined so far
+:type <expr>    show the type of the value of an expression
+:time <expr>    evaluate an expression and show how long it took
+:load <file>    run a file in the session
+:reset          forget everything defined in the session
+:ast <expr>     show how an expression is parsed
+:doc <name>     show what a name is, with the docs of functions and macros
+:save <file>    write the definitions of the session to a .ul file
+:quit           leave, like `quit` or Ctrl-D";
+
+/// Forms whose effect `:save` keeps
+const DEFINING_FORMS: [&str; 6] = ["var", "set!", "func", "defmacro", "import", "export"];
+
+/// `a b & rest`
+fn params(function: &Function) -> String {
+    let mut params: Vec<&str> = function.arg_names().iter().map(String::as_str).collect();
+
+    if let Some(rest) = function.rest() {
+        params.extend(["&", rest]);
+    }
+
+    params.join(" ")
+}
+
+/// `(name a b & rest)`
+fn signature(name: &str, function: &Function) -> String {
+    match params(function).as_str() {
+        "" => format!("({})", name),
+        params => format!("({} {})", name, params),
+    }
+}
+
+/// Names of the meta-commands, `:help` first.
+pub fn commands() -> impl Iterator<Item = &'static str> {
+    HELP.lines()
+        .filter_map(|help| help.split_whitespace().next())
+}
+
+fn show(result: Result<Token, uncommon_lisp::Error>) {
+    match result {
+        Ok(value) => println!("{}", value.repr()),
+        Err(error) => println!("{}", error),
+    }
+}
+
+/// Prints the tree of a parsed form, one token per line.
+fn print_ast(token: &Token, depth: usize) {
+    let indent = "  ".repeat(depth);
+
+    match token {
+        Token::Expression(Expression { fid, args, .. }) => {
+            match fid {
+                Some(id) => println!("{}expression {}", indent, id.0),
+                None => println!("{}expression", indent),
+            }
+
+            for arg in args.iter() {
+                print_ast(arg, depth + 1);
+            }
+        }
+        Token::List(items) => {
+            println!("{}list", indent);
+
+            for item in items.iter() {
+                print_ast(item, depth + 1);
+            }
+        }
+        Token::Object(object) => {
+            println!("{}object", indent);
+
+            for (key, value) in object.iter() {
+                println!("{}  key {}", indent, key);
+                print_ast(value, depth + 2);
+            }
+        }
+        token => println!("{}{} {}", indent, token.as_type(), token.repr()),
+    }
+}
+
+/// An interpreter together with what the REPL remembers about its input.
+pub struct Session {
+    interpreter: Interpreter,
+    /// Top-level definitions evaluated without errors, in order
+    definitions: Vec<String>,
+}
+
+impl Session {
+    pub fn new(interpreter: Interpreter) -> Self {
+        Self {
+            interpreter,
+            definitions: Vec::new(),
+        }
+    }
+
+    /// Every name an identifier typed at the prompt may refer to, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let interpreter = &self.interpreter;
+
+        let mut names: Vec<String> = Interpreter::special_forms()
+            .into_iter()
+            .chain(interpreter.builtin_names())
+            .chain(interpreter.functions().iter().map(|f| f.name().to_string()))
+            .chain(interpreter.macros().iter().map(|m| m.name().to_string()))
+            .chain(interpreter.globals().into_iter().map(|(name, _)| name))
+            .collect();
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Parameters of the functions and macros defined in the session, by
+    /// name.
+    pub fn parameters(&self) -> HashMap<String, String> {
+        let interpreter = &self.interpreter;
+
+        let variables = interpreter
+            .globals()
+            .into_iter()
+            .filter_map(|(name, value)| match value {
+                Token::Function(function) => Some((name, params(&function))),
+                _ => None,
+            });
+
+        interpreter
+            .functions()
+            .into_iter()
+            .chain(interpreter.macros())
+            .map(|function| (function.name().to_string(), params(&function)))
+            .chain(variables)
+            .collect()
+    }
+
+    /// Evaluates `source` and shows its value.
+    pub fn run(&mut self, source: &str) {
+        show(self.eval(source));
+    }
+
+    /// Evaluates `source`, remembering each definition in it once it ran
+    /// without errors.
+    fn eval(&mut self, source: &str) -> Result<Token, uncommon_lisp::Error> {
+        let expressions = Parser::with_name("<repl>", source).parse_expressions()?;
+        let mut value = Token::Nil;
+
+        for e in expressions {
+            value = self.interpreter.eval_expression(&e)?;
+            self.record(&e);
+        }
+
+        Ok(value)
+    }
+
+    /// Remembers `e` if it is a definition.
+    fn record(&mut self, e: &Expression) {
+        if e.fid
+            .as_ref()
+            .is_some_and(|id| DEFINING_FORMS.contains(&id.as_str()))
+        {
+            self.definitions.push(e.to_string());
+        }
+    }
+
+    /// Shows the type of the value of `source`. The expression runs in the
+    /// session, so a definition in it is remembered like one typed at the
+    /// prompt.
+    fn type_of(&mut self, source: &str) -> Result<(), Box<dyn Error>> {
+        let typeof_ = format!("(typeof {})", source);
+
+        for e in Parser::with_name("<repl>", &typeof_).parse_expressions()? {
+            println!("{}", self.interpreter.eval_expression(&e)?);
+
+            for arg in e.args.iter() {
+                if let Token::Expression(defined) = arg {
+                    self.record(defined);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the meta-command `line` starts with, `false` when it asks to
+    /// leave.
+    pub fn command(&mut self, line: &str) -> bool {
+        let (name, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arg = arg.trim();
+
+        let result = match name {
+            ":quit" => return false,
+            ":help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            ":env" => {
+                self.env();
+                Ok(())
+            }
+            ":reset" => {
+                self.interpreter = Interpreter::with_engine(self.interpreter.engine());
+                self.definitions.clear();
+                println!("session reset");
+                Ok(())
+            }
+            _ if arg.is_empty() => match HELP
+                .lines()
+                .find(|help| help.split_whitespace().next() == Some(name))
+            {
+                Some(help) => Err(format!("usage: {}", help).into()),
+                None => Err(unknown(name)),
+            },
+            ":type" => self.type_of(arg),
+            ":time" => {
+                let start = Instant::now();
+                let result = self.eval(arg);
+                let elapsed = start.elapsed();
+
+                show(result);
+                println!("time: {:?}", elapsed);
+                Ok(())
+            }
+            ":load" => self.load(arg),
+            ":ast" => Parser::with_name("<repl>", arg)
+                .parse_forms()
+                .map(|forms| forms.iter().for_each(|form| print_ast(form, 0)))
+                .map_err(Into::into),
+            ":doc" => {
+                println!("{}", self.doc(arg));
+                Ok(())
+            }
+            ":save" => self.save(arg),
+            _ => Err(unknown(name)),
+        };
+
+        if let Err(error) = result {
+            println!("{}", error);
+        }
+
+        true
+    }
+
+    fn env(&self) {
+        let interpreter = &self.interpreter;
+        let mut empty = true;
+
+        let variables = interpreter.globals();
+
+        if !variables.is_empty() {
+            println!("variables:");
+
+            for (name, value) in variables {
+                println!("  {} = {}", name, value.repr());
+            }
+
+            empty = false;
+        }
+
+        for (title, functions) in [
+            ("functions", interpreter.functions()),
+            ("macros", interpreter.macros()),
+        ] {
+            if !functions.is_empty() {
+                println!("{}:", title);
+
+                for function in functions {
+                    println!("  {}", signature(function.name(), &function));
+                }
+
+                empty = false;
+            }
+        }
+
+        if empty {
+            println!("nothing is defined yet");
+        }
+    }
+
+    /// Runs a file, `:save` loads it again by its full path so the saved
+    /// session works from any directory.
+    fn load(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let value = self.interpreter.eval_file(path)?;
+        let path = fs::canonicalize(path)?.display().to_string();
+
+        self.definitions
+            .push(format!("(load {})", Token::String(path).repr()));
+        println!("{}", value.repr());
+
+        Ok(())
+    }
+
+    /// What `name` is, with the signature and documentation of functions.
+    fn doc(&self, name: &str) -> String {
+        let interpreter = &self.interpreter;
+
+        if Interpreter::is_special_form(name) {
+            return format!("{} is a special form", name);
+        }
+
+        if let Some(m) = interpreter.macros().iter().find(|m| m.name() == name) {
+            return describe("macro", &signature(name, m), m.doc());
+        }
+
+        let function = match interpreter.get_global(name) {
+            Token::Function(function) => Some(function),
+            _ => None,
+        };
+
+        // Builtins have no signature and most of them no documentation
+        match function {
+            Some(function) if function.builtin().is_some() => {
+                return match function.doc() {
+                    Some(doc) => describe("builtin", name, Some(doc)),
+                    None => format!("{} is a builtin", name),
+                };
+            }
+            Some(function) => {
+                return describe("function", &signature(name, &function), function.doc());
+            }
+            None => (),
+        }
+
+        let value = interpreter.get_global(name);
+
+        match value != Token::Nil || interpreter.globals().iter().any(|(n, _)| n == name) {
+            true => format!("{} is a variable holding a {}", name, value.as_type()),
+            false => format!("{} is not defined", name),
+        }
+    }
+
+    fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut file = PathBuf::from(path);
+
+        if file.extension().is_none() {
+            file.set_extension("ul");
+        }
+
+        let mut contents = self.definitions.join("\n");
+        contents.push('\n');
+        fs::write(&file, contents)?;
+
+        println!(
+            "saved {} definitions to {}",
+            self.definitions.len(),
+            file.display()
+        );
+
+        Ok(())
+    }
+}
+
+fn describe(kind: &str, signature: &str, doc: Option<&str>) -> String {
+    format!(
+        "{} {}\n  {}",
+        kind,
+        signature,
+        doc.unwrap_or("no documentation")
+    )
+}
+
+fn unknown(name: &str) -> Box<dyn Error> {
+    format!("unknown command `{}`, see :help", name).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn definitions_are_recorded_as_they_run() {
+        let mut session = Session::new(Interpreter::new());
+
+        session.run("(var a 1) (func f [] a) (print \"\")");
+        session.run("(var b 2) (nosuch) (var c 3)");
+        session.run("(set! a 5)");
+
+        assert_eq!(
+            session.definitions,
+            ["(var a 1)", "(func f [] a)", "(var b 2)", "(set! a 5)"]
+        );
+    }
+
+    #[test]
+    fn definitions_made_by_type_are_recorded() {
+        let mut session = Session::new(Interpreter::new());
+
+        session.command(":type (func g [] 1)");
+        session.command(":type (var d (nosuch))");
+        session.command(":type g");
+
+        assert_eq!(session.definitions, ["(func g [] 1)"]);
+    }
+
+    #[test]
+    fn loads_are_recorded_by_full_path() {
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("ul-load-{}.ul", std::process::id()));
+        fs::write(&file, "(var loaded 1)").unwrap();
+
+        let mut session = Session::new(Interpreter::new());
+        let typed = dir.join(".").join(file.file_name().unwrap());
+        session.command(&format!(":load {}", typed.display()));
+        fs::remove_file(&file).unwrap();
+
+        let full = fs::canonicalize(dir)
+            .unwrap()
+            .join(file.file_name().unwrap());
+        assert_eq!(
+            session.definitions,
+            [format!(
+                "(load {})",
+                Token::String(full.display().to_string()).repr()
+            )]
+        );
+    }
+
+    #[test]
+    fn save() {
+        let mut session = Session::new(Interpreter::new());
+        session.run("(func double [x] \"Doubles x.\" (mul x 2)) (double 2)");
+
+        let path = std::env::temp_dir().join(format!("ul-save-{}", std::process::id()));
+        session.command(&format!(":save {}", path.display()));
+
+        let file = path.with_extension("ul");
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "(func double [x] \"Doubles x.\" (mul x 2))\n"
+        );
+
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn doc() {
+        let mut session = Session::new(Interpreter::new());
+        session.run("(func double [x] \"Doubles x.\" (mul x 2))");
+
+        assert_eq!(session.doc("double"), "function (double x)\n  Doubles x.");
+        assert_eq!(session.doc("add"), "add is a builtin");
+        assert_eq!(session.doc("if"), "if is a special form");
+        assert_eq!(session.doc("nothing"), "nothing is not defined");
+    }
+}
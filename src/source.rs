@@ -0,0 +1,139 @@
+use std::{fmt::Debug, rc::Rc};
+
+/// A parsed text kept alive by the spans pointing into it, so diagnostics can
+/// quote it later.
+pub struct SourceFile {
+    name: String,
+    text: String,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, text: &str) -> Rc<Self> {
+        Rc::new(Self {
+            name: name.to_string(),
+            text: text.to_string(),
+        })
+    }
+}
+
+impl Debug for SourceFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SourceFile({})", self.name)
+    }
+}
+
+/// Byte range `start..end` inside a source file. Spans without a file mark
+/// synthesized code.
+#[derive(Debug, Clone, Default)]
+pub struct Span {
+    pub file: Option<Rc<SourceFile>>,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(file: Option<Rc<SourceFile>>, start: usize, end: usize) -> Self {
+        Self { file, start, end }
+    }
+}
+
+/// Spans are equal when they cover the same range of the same file.
+impl PartialEq for Span {
+    fn eq(&self, other: &Self) -> bool {
+        let same_file = match (&self.file, &other.file) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+
+        same_file && self.start == other.start && self.end == other.end
+    }
+}
+
+impl Eq for Span {}
+
+/// 1-based line and column of `span.start`, columns are counted in chars.
+pub fn line_col(span: &Span) -> Option<(usize, usize)> {
+    let text = &span.file.as_ref()?.text;
+    let before = text.get(..span.start)?;
+
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+
+    Some((line, column))
+}
+
+/// `file:line:col` of `span.start`
+pub fn location(span: &Span) -> Option<String> {
+    let (line, column) = line_col(span)?;
+
+    Some(format!("{}:{}:{}", span.file.as_ref()?.name, line, column))
+}
+
+/// Renders a rustc-style report: the message, `file:line:col`, the offending
+/// source line and a caret underline. Falls back to the bare message when the
+/// span doesn't point into a known file.
+pub fn render(message: &str, span: &Span) -> String {
+    let header = format!("error: {}", message);
+
+    let (Some((line, column)), Some(file)) = (line_col(span), &span.file) else {
+        return header;
+    };
+
+    let line_start = file.text[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = file.text[span.start..]
+        .find('\n')
+        .map_or(file.text.len(), |i| span.start + i);
+    let source_line = &file.text[line_start..line_end];
+
+    let underline_end = span.end.clamp(span.start, line_end);
+    let width = file.text[span.start..underline_end].chars().count().max(1);
+
+    let gutter = " ".repeat(line.to_string().len());
+
+    // Tabs stay tabs so the caret lines up however wide they are shown
+    let padding: String = file.text[line_start..span.start]
+        .chars()
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    format!(
+        "{header}\n{gutter}--> {}:{line}:{column}\n{gutter} |\n{line} | {source_line}\n{gutter} | {padding}{}",
+        file.name,
+        "^".repeat(width),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caret_report() {
+        let file = SourceFile::new("test.ul", "(var a 1)\n(add a \"b\")\n");
+        let span = Span::new(Some(file), 10, 21);
+
+        assert_eq!(line_col(&span), Some((2, 1)));
+        assert_eq!(
+            render("type mismatch", &span),
+            "error: type mismatch\n --> test.ul:2:1\n  |\n2 | (add a \"b\")\n  | ^^^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn caret_after_tabs() {
+        let file = SourceFile::new("test.ul", "\t(add\t1 \"b\")");
+        let span = Span::new(Some(file), 8, 11);
+
+        assert_eq!(
+            render("type mismatch", &span),
+            "error: type mismatch\n --> test.ul:1:9\n  |\n1 | \t(add\t1 \"b\")\n  | \t    \t  ^^^"
+        );
+    }
+
+    #[test]
+    fn unknown_file() {
+        assert_eq!(render("oops", &Span::default()), "error: oops");
+    }
+}
@@ -0,0 +1,478 @@
+use std::collections::VecDeque;
+
+use crate::{
+    builtins::BuiltinFunc,
+    errors::RuntimeError,
+    scope::{Function, Scope},
+    token::{List, Number, Token},
+};
+
+use super::{arguments, index};
+
+/// List builtins. Lists are values: the functions that change a list return
+/// an updated copy and leave their arguments untouched. Higher-order ones
+/// take the function first, `(map f list)`.
+pub const FUNCTIONS: &[(&str, BuiltinFunc)] = &[
+    ("len", len),
+    ("nth", nth),
+    ("first", first),
+    ("rest", rest),
+    ("push", push),
+    ("pop", pop),
+    ("append", append),
+    ("slice", slice),
+    ("reverse", reverse),
+    ("range", range),
+    ("contains", contains),
+    ("sort", sort),
+    ("zip", zip),
+    ("flatten", flatten),
+    // Higher-order
+    ("map", map),
+    ("filter", filter),
+    ("reduce", reduce),
+    ("each", each),
+    ("any", any),
+    ("all", all),
+];
+
+fn list(token: Token, scope: &Scope) -> Result<VecDeque<Token>, RuntimeError> {
+    token.extract::<List>(scope).map(|list| list.0)
+}
+
+fn new_list(items: impl IntoIterator<Item = Token>) -> Token {
+    Token::List(List::from_iterable(items))
+}
+
+/// Calls `f` with `args`, telling whether the result is truthy.
+fn test(f: &Function, args: Vec<Token>, scope: &Scope) -> Result<bool, RuntimeError> {
+    f.clone().apply(args)?.extract(scope)
+}
+
+/// Extracts the function and the list of `(name f list)`.
+fn function_and_list(
+    tokens: List,
+    scope: &Scope,
+) -> Result<(Function, VecDeque<Token>), RuntimeError> {
+    let mut args = arguments(tokens, 2, 2)?;
+    let f: Function = args.next().unwrap().extract(scope)?;
+
+    Ok((f, list(args.next().unwrap(), scope)?))
+}
+
+/// The number of items of a list, characters of a string or keys of an
+/// object.
+pub fn len(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, 1)?;
+
+    let len = match args.next().unwrap() {
+        Token::List(list) => list.len(),
+        Token::Object(object) => object.len(),
+        token => token.extract::<String>(scope)?.chars().count(),
+    };
+
+    Ok(Token::Number(Number::Int(len as i64)))
+}
+
+/// `(nth list i)` is the item at `i`, `nil` past the end of the list.
+pub fn nth(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 2, 2)?;
+    let mut items = list(args.next().unwrap(), scope)?;
+    let i = index(args.next().unwrap(), scope)?;
+
+    Ok(items.remove(i).unwrap_or(Token::Nil))
+}
+
+pub fn first(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, 1)?;
+    let mut items = list(args.next().unwrap(), scope)?;
+
+    Ok(items.pop_front().unwrap_or(Token::Nil))
+}
+
+/// Everything but the first item, the empty list stays empty.
+pub fn rest(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, 1)?;
+    let mut items = list(args.next().unwrap(), scope)?;
+
+    items.pop_front();
+    Ok(new_list(items))
+}
+
+/// `(push list item...)` adds items to the end of a list.
+pub fn push(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, usize::MAX)?;
+    let mut items = list(args.next().unwrap(), scope)?;
+
+    items.extend(args);
+    Ok(new_list(items))
+}
+
+/// Everything but the last item, the empty list stays empty.
+pub fn pop(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, 1)?;
+    let mut items = list(args.next().unwrap(), scope)?;
+
+    items.pop_back();
+    Ok(new_list(items))
+}
+
+/// Concatenates lists.
+pub fn append(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut items = VecDeque::new();
+
+    for token in tokens.0 {
+        items.extend(list(token, scope)?);
+    }
+
+    Ok(new_list(items))
+}
+
+/// `(slice list start [end])` takes the items from `start` up to, but not
+/// including, `end` or the end of the list.
+pub fn slice(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 2, 3)?;
+    let items = list(args.next().unwrap(), scope)?;
+    let start = index(args.next().unwrap(), scope)?;
+
+    let end = match args.next() {
+        Some(end) => index(end, scope)?,
+        None => items.len(),
+    };
+
+    if end > items.len() {
+        return Err(RuntimeError::InvalidValue(format!(
+            "index {} is out of range for a list of {} items",
+            end,
+            items.len()
+        )));
+    }
+
+    if start > end {
+        return Err(RuntimeError::InvalidValue(format!(
+            "slice start {} is after its end {}",
+            start, end
+        )));
+    }
+
+    Ok(new_list(items.range(start..end).cloned()))
+}
+
+pub fn reverse(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, 1)?;
+    let items = list(args.next().unwrap(), scope)?;
+
+    Ok(new_list(items.into_iter().rev()))
+}
+
+/// `(range end)` counts from 0 up to `end`, `(range start end [step])` goes
+/// by `step`. `end` is never included.
+pub fn range(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, 3)?
+        .map(|token| token.extract::<Number>(scope))
+        .collect::<Result<VecDeque<_>, _>>()?;
+
+    let start = match args.len() {
+        1 => Number::Int(0),
+        _ => args.pop_front().unwrap(),
+    };
+    let end = args.pop_front().unwrap();
+    let step = args.pop_front().unwrap_or(Number::Int(1));
+
+    if step.is_zero() {
+        return Err(RuntimeError::InvalidValue(
+            "range step can't be zero".to_string(),
+        ));
+    }
+
+    let mut items = VecDeque::new();
+    let mut i = start;
+
+    while i.in_range(&end, &step) {
+        items.push_back(Token::Number(i.clone()));
+        i = i.add(&step);
+    }
+
+    Ok(new_list(items))
+}
+
+/// `(contains list item)` tells whether a list has an item equal to `item`,
+/// `(contains s part)` whether a string contains `part`.
+pub fn contains(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 2, 2)?;
+    let haystack = args.next().unwrap();
+    let needle = args.next().unwrap();
+
+    Ok(Token::Bool(match haystack {
+        Token::List(items) => items.contains(&needle),
+        Token::String(s) => s.contains(&needle.extract::<String>(scope)?),
+        token => {
+            return Err(RuntimeError::TypeMismatch {
+                expected: "list".to_string(),
+                found: token.as_type(),
+            });
+        }
+    }))
+}
+
+/// Stable merge sort with a fallible `less`: a failing comparator stops the
+/// sort, and one that isn't a consistent order can't make it panic.
+fn merge_sort(
+    mut items: Vec<Token>,
+    less: &mut impl FnMut(&Token, &Token) -> Result<bool, RuntimeError>,
+) -> Result<Vec<Token>, RuntimeError> {
+    if items.len() < 2 {
+        return Ok(items);
+    }
+
+    let right = merge_sort(items.split_off(items.len() / 2), less)?;
+    let left = merge_sort(items, less)?;
+
+    let mut sorted = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        match less(b, a)? {
+            true => sorted.push(right.next().unwrap()),
+            false => sorted.push(left.next().unwrap()),
+        }
+    }
+
+    sorted.extend(left.chain(right));
+    Ok(sorted)
+}
+
+/// `(sort list [less])` sorts numbers and strings in ascending order, or any
+/// items by `less`, a function telling whether its first argument goes
+/// before its second one. Equal items keep their order.
+pub fn sort(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, 2)?;
+    let items = list(args.next().unwrap(), scope)?;
+
+    let sorted = match args.next() {
+        Some(less) => {
+            let less: Function = less.extract(scope)?;
+            merge_sort(items.into(), &mut |a, b| {
+                test(&less, vec![a.clone(), b.clone()], scope)
+            })?
+        }
+        None => merge_sort(items.into(), &mut |a, b| match a.partial_cmp(b) {
+            Some(ordering) if a.as_type() == b.as_type() => Ok(ordering.is_lt()),
+            _ => Err(RuntimeError::InvalidValue(format!(
+                "can't compare {} with {}",
+                a.as_type(),
+                b.as_type()
+            ))),
+        })?,
+    };
+
+    Ok(new_list(sorted))
+}
+
+/// `(zip a b ...)` pairs up the items of lists, stopping at the end of the
+/// shortest one.
+pub fn zip(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let lists: Vec<VecDeque<Token>> = arguments(tokens, 1, usize::MAX)?
+        .map(|token| list(token, scope))
+        .collect::<Result<_, _>>()?;
+
+    let len = lists.iter().map(VecDeque::len).min().unwrap();
+
+    Ok(new_list((0..len).map(|i| {
+        new_list(lists.iter().map(|items| items[i].clone()))
+    })))
+}
+
+fn flatten_into(items: VecDeque<Token>, flat: &mut VecDeque<Token>) {
+    for item in items {
+        match item {
+            Token::List(nested) => flatten_into(nested.0, flat),
+            item => flat.push_back(item),
+        }
+    }
+}
+
+/// Splices the items of nested lists, at any depth, into one list.
+pub fn flatten(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, 1)?;
+    let mut flat = VecDeque::new();
+
+    flatten_into(list(args.next().unwrap(), scope)?, &mut flat);
+    Ok(new_list(flat))
+}
+
+pub fn map(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let (f, items) = function_and_list(tokens, scope)?;
+
+    items
+        .into_iter()
+        .map(|item| f.clone().apply(vec![item]))
+        .collect::<Result<Vec<_>, _>>()
+        .map(new_list)
+}
+
+pub fn filter(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let (f, items) = function_and_list(tokens, scope)?;
+    let mut kept = VecDeque::new();
+
+    for item in items {
+        if test(&f, vec![item.clone()], scope)? {
+            kept.push_back(item);
+        }
+    }
+
+    Ok(new_list(kept))
+}
+
+/// `(reduce f init list)` folds a list from left to right, `(reduce f list)`
+/// starts with its first item.
+pub fn reduce(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 2, 3)?;
+    let f: Function = args.next().unwrap().extract(scope)?;
+    let first = args.next().unwrap();
+
+    let (init, mut items) = match args.next() {
+        Some(items) => (first, list(items, scope)?),
+        None => {
+            let mut items = list(first, scope)?;
+
+            let Some(init) = items.pop_front() else {
+                return Err(RuntimeError::InvalidValue(
+                    "can't reduce an empty list without an initial value".to_string(),
+                ));
+            };
+
+            (init, items)
+        }
+    };
+
+    items
+        .drain(..)
+        .try_fold(init, |acc, item| f.clone().apply(vec![acc, item]))
+}
+
+/// Calls a function on every item for its side effects.
+pub fn each(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let (f, items) = function_and_list(tokens, scope)?;
+
+    for item in items {
+        f.clone().apply(vec![item])?;
+    }
+
+    Ok(Token::Nil)
+}
+
+pub fn any(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let (f, items) = function_and_list(tokens, scope)?;
+
+    for item in items {
+        if test(&f, vec![item], scope)? {
+            return Ok(Token::Bool(true));
+        }
+    }
+
+    Ok(Token::Bool(false))
+}
+
+pub fn all(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let (f, items) = function_and_list(tokens, scope)?;
+
+    for item in items {
+        if !test(&f, vec![item], scope)? {
+            return Ok(Token::Bool(false));
+        }
+    }
+
+    Ok(Token::Bool(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stdlib::eval;
+
+    #[test]
+    fn functions() {
+        let cases = [
+            ("(len [1 2 3])", "3"),
+            ("(len \"日本\")", "2"),
+            ("(len {a 1})", "1"),
+            ("(nth [1 2 3] 1)", "2"),
+            ("(nth [1 2 3] 5)", "nil"),
+            ("(first [])", "nil"),
+            ("(join (rest [1 2 3]) \",\")", "2,3"),
+            ("(join (push [1] 2 3) \",\")", "1,2,3"),
+            ("(join (pop [1 2 3]) \",\")", "1,2"),
+            ("(join (append [1] [] [2 3]) \",\")", "1,2,3"),
+            ("(join (slice [1 2 3 4] 1 3) \",\")", "2,3"),
+            ("(join (reverse [1 2 3]) \",\")", "3,2,1"),
+            ("(join (range 3) \",\")", "0,1,2"),
+            ("(join (range 5 0 -2) \",\")", "5,3,1"),
+            ("(contains [1 \"a\"] \"a\")", "true"),
+            ("(contains \"hello\" \"ell\")", "true"),
+            ("(join (sort [3 1.5 2]) \",\")", "1.5,2,3"),
+            ("(join (flatten [1 [2 [3 []]] 4]) \",\")", "1,2,3,4"),
+            (
+                "(join (map (fn [p] (join p \"\")) (zip [1 2 3] [\"a\" \"b\"])) \",\")",
+                "1a,2b",
+            ),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(eval(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn higher_order() {
+        let cases = [
+            (
+                "(do (func double [x] (mul x 2)) (join (map double [1 2 3]) \",\"))",
+                "2,4,6",
+            ),
+            ("(join (filter (fn [x] (gt x 1)) [1 2 3]) \",\")", "2,3"),
+            ("(reduce (fn [acc x] (add acc x)) 0 (range 101))", "5050"),
+            (
+                "(reduce (fn [acc x] (concat acc x)) [\"a\" \"b\" \"c\"])",
+                "abc",
+            ),
+            ("(any (fn [x] (eq x 2)) [1 2 3])", "true"),
+            ("(all (fn [x] (gt x 1)) [1 2 3])", "false"),
+            ("(all (fn [x] false) [])", "true"),
+            ("(each (fn [x] x) [1 2])", "nil"),
+            (
+                "(join (sort [\"bb\" \"a\" \"ccc\"] (fn [a b] (gt (len a) (len b)))) \",\")",
+                "ccc,bb,a",
+            ),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(eval(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn builtins_as_values() {
+        let cases = [
+            ("(reduce add 0 [1 2 3])", "6"),
+            ("(join (map abs [-1 2 -3]) \",\")", "1,2,3"),
+            ("(do (var f upper) (f \"a\"))", "A"),
+            ("(call mul 2 3)", "6"),
+            ("(do add)", "<builtin add>"),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(eval(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert!(eval("(sort [1 \"a\"])").contains("can't compare"));
+        assert!(eval("(slice [1 2] 1 3)").contains("out of range"));
+        assert!(eval("(range 0 1 0)").contains("zero"));
+        assert!(eval("(reduce (fn [a b] a) [])").contains("empty list"));
+        assert!(eval("(map 1 [1])").contains("function"));
+        assert!(eval("(sort [2 1] (fn [a b] (throw \"no\")))").contains("no"));
+    }
+}
@@ -0,0 +1,486 @@
+use std::{cmp::Ordering, f64::consts};
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::FromPrimitive;
+
+use crate::{
+    builtins::BuiltinFunc,
+    errors::RuntimeError,
+    scope::Scope,
+    token::{List, Number, Token},
+};
+
+/// Math builtins except the division ones, see [`division_functions`].
+pub const FUNCTIONS: &[(&str, BuiltinFunc)] = &[
+    ("add", add),
+    ("sub", sub),
+    ("mul", mul),
+    ("pow", pow),
+    ("abs", abs),
+    ("min", min),
+    ("max", max),
+    ("clamp", clamp),
+    ("gcd", gcd),
+    ("lcm", lcm),
+    // Conversions
+    ("int", to_int),
+    ("float", to_float),
+    ("floor", floor),
+    ("ceil", ceil),
+    ("round", round),
+    ("int?", is_int),
+    ("float?", is_float),
+    // Floating point
+    ("sqrt", sqrt),
+    ("exp", exp),
+    ("log", log),
+    ("sin", sin),
+    ("cos", cos),
+    ("tan", tan),
+    ("asin", asin),
+    ("acos", acos),
+    ("atan", atan),
+];
+
+/// Values of the names scripts can use without defining them.
+pub fn constant(name: &str) -> Option<Token> {
+    match name {
+        "pi" => Some(Token::Number(Number::Float(consts::PI))),
+        "e" => Some(Token::Number(Number::Float(consts::E))),
+        _ => None,
+    }
+}
+
+/// What dividing by zero does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DivisionByZero {
+    /// Raise an `invalid-value` error
+    #[default]
+    Error,
+    /// Follow IEEE 754: `±inf`, or `NaN` for `0/0`. Integer division by zero
+    /// still fails as there's no infinite integer.
+    Infinity,
+}
+
+/// `div`, `quot`, `rem` and `mod` behaving as `mode` says.
+pub fn division_functions(mode: DivisionByZero) -> [(&'static str, BuiltinFunc); 4] {
+    match mode {
+        DivisionByZero::Error => [("div", div), ("quot", quot), ("rem", rem), ("mod", modulo)],
+        DivisionByZero::Infinity => [
+            ("div", ieee_div),
+            ("quot", ieee_quot),
+            ("rem", ieee_rem),
+            ("mod", ieee_modulo),
+        ],
+    }
+}
+
+/// Extracts exactly `N` numbers.
+fn numbers<const N: usize>(tokens: List, scope: &Scope) -> Result<[Number; N], RuntimeError> {
+    if tokens.len() != N {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: N,
+            got: tokens.len(),
+        });
+    }
+
+    let mut numbers = Vec::with_capacity(N);
+
+    for token in tokens.0 {
+        numbers.push(token.extract(scope)?);
+    }
+
+    Ok(numbers.try_into().unwrap())
+}
+
+/// Folds the numbers in `tokens` from left to right with `op`, starting with
+/// `identity` or with the first number when the operation has none.
+fn arithmetic(
+    tokens: List,
+    scope: &Scope,
+    identity: Option<Number>,
+    op: fn(&Number, &Number) -> Result<Number, RuntimeError>,
+) -> Result<Token, RuntimeError> {
+    let mut tokens = tokens.0.into_iter();
+
+    let base = match identity {
+        Some(identity) => identity,
+        None if tokens.len() < 2 => return Err(RuntimeError::NotEnoughArgs { min: 2 }),
+        None => tokens.next().unwrap().extract(scope)?,
+    };
+
+    tokens
+        .try_fold(base, |acc, token| op(&acc, &token.extract(scope)?))
+        .map(Token::Number)
+}
+
+fn binary(
+    tokens: List,
+    scope: &Scope,
+    op: fn(&Number, &Number) -> Result<Number, RuntimeError>,
+) -> Result<Token, RuntimeError> {
+    let [a, b] = numbers(tokens, scope)?;
+    op(&a, &b).map(Token::Number)
+}
+
+fn unary(
+    tokens: List,
+    scope: &Scope,
+    op: fn(Number) -> Result<Number, RuntimeError>,
+) -> Result<Token, RuntimeError> {
+    let [n] = numbers(tokens, scope)?;
+    op(n).map(Token::Number)
+}
+
+/// Applies a function defined on floats, integers are converted.
+fn float(tokens: List, scope: &Scope, op: fn(f64) -> f64) -> Result<Token, RuntimeError> {
+    let [n] = numbers(tokens, scope)?;
+    Ok(Token::Number(Number::Float(op(n.to_f64()))))
+}
+
+/// Rounds a float to an integer with `op`, integers are left as they are.
+fn rounding(tokens: List, scope: &Scope, op: fn(f64) -> f64) -> Result<Token, RuntimeError> {
+    let [n] = numbers(tokens, scope)?;
+
+    let Number::Float(value) = n else {
+        return Ok(Token::Number(n));
+    };
+
+    match BigInt::from_f64(op(value)) {
+        Some(value) => Ok(Token::Number(Number::big(value))),
+        None => Err(RuntimeError::InvalidValue(format!(
+            "{} has no integer value",
+            n
+        ))),
+    }
+}
+
+fn nonzero(divisor: &Number) -> Result<(), RuntimeError> {
+    match divisor.is_zero() {
+        true => Err(RuntimeError::InvalidValue("division by zero".to_string())),
+        false => Ok(()),
+    }
+}
+
+pub fn add(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    arithmetic(tokens, scope, Some(Number::Int(0)), |a, b| Ok(a.add(b)))
+}
+
+pub fn sub(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    arithmetic(tokens, scope, None, |a, b| Ok(a.sub(b)))
+}
+
+pub fn mul(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    arithmetic(tokens, scope, Some(Number::Int(1)), |a, b| Ok(a.mul(b)))
+}
+
+/// True division, `(div 7 2)` is `3.5`. See `quot` for integer division.
+pub fn div(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    arithmetic(tokens, scope, None, |a, b| {
+        nonzero(b)?;
+        Ok(a.div(b))
+    })
+}
+
+pub fn quot(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    binary(tokens, scope, |a, b| {
+        nonzero(b)?;
+        a.quot(b)
+    })
+}
+
+pub fn rem(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    binary(tokens, scope, |a, b| {
+        nonzero(b)?;
+        a.rem(b)
+    })
+}
+
+pub fn modulo(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    binary(tokens, scope, |a, b| {
+        nonzero(b)?;
+        a.modulo(b)
+    })
+}
+
+pub fn ieee_div(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    arithmetic(tokens, scope, None, |a, b| Ok(a.div(b)))
+}
+
+pub fn ieee_quot(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    binary(tokens, scope, Number::quot)
+}
+
+pub fn ieee_rem(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    binary(tokens, scope, Number::rem)
+}
+
+pub fn ieee_modulo(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    binary(tokens, scope, Number::modulo)
+}
+
+pub fn pow(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    binary(tokens, scope, |a, b| Ok(a.pow(b)))
+}
+
+pub fn abs(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    unary(tokens, scope, |n| match n.is_negative() {
+        true => Ok(n.neg()),
+        false => Ok(n),
+    })
+}
+
+/// The first of the numbers no other one is `ordering` than.
+fn extreme(tokens: List, scope: &Scope, ordering: Ordering) -> Result<Token, RuntimeError> {
+    let mut tokens = tokens.0.into_iter();
+
+    let Some(first) = tokens.next() else {
+        return Err(RuntimeError::NotEnoughArgs { min: 1 });
+    };
+
+    let mut best: Number = first.extract(scope)?;
+
+    for token in tokens {
+        let n: Number = token.extract(scope)?;
+
+        if n.partial_cmp(&best) == Some(ordering) {
+            best = n;
+        }
+    }
+
+    Ok(Token::Number(best))
+}
+
+pub fn min(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    extreme(tokens, scope, Ordering::Less)
+}
+
+pub fn max(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    extreme(tokens, scope, Ordering::Greater)
+}
+
+/// `(clamp x low high)` limits `x` to the range from `low` to `high`.
+pub fn clamp(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let [x, low, high] = numbers(tokens, scope)?;
+
+    if low > high {
+        return Err(RuntimeError::InvalidValue(format!(
+            "clamp range {}..{} is empty",
+            low, high
+        )));
+    }
+
+    Ok(Token::Number(match x {
+        x if x < low => low,
+        x if x > high => high,
+        x => x,
+    }))
+}
+
+/// Folds integer arguments with `op`, starting from `identity`.
+fn integers(
+    tokens: List,
+    scope: &Scope,
+    identity: i64,
+    op: fn(&BigInt, &BigInt) -> BigInt,
+) -> Result<Token, RuntimeError> {
+    let mut acc = BigInt::from(identity);
+
+    for token in tokens.0 {
+        let n: Number = token.extract(scope)?;
+
+        let Some(n) = n.to_big() else {
+            return Err(RuntimeError::TypeMismatch {
+                expected: "integer".to_string(),
+                found: format!("float {}", n),
+            });
+        };
+
+        acc = op(&acc, &n);
+    }
+
+    Ok(Token::Number(Number::big(acc)))
+}
+
+pub fn gcd(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    integers(tokens, scope, 0, BigInt::gcd)
+}
+
+pub fn lcm(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    integers(tokens, scope, 1, BigInt::lcm)
+}
+
+/// `(int 2.7)` truncates a number to an integer, `(float 2)` makes it a
+/// float.
+pub fn to_int(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    rounding(tokens, scope, f64::trunc)
+}
+
+pub fn to_float(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    float(tokens, scope, |value| value)
+}
+
+pub fn floor(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    rounding(tokens, scope, f64::floor)
+}
+
+pub fn ceil(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    rounding(tokens, scope, f64::ceil)
+}
+
+/// Rounds half away from zero.
+pub fn round(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    rounding(tokens, scope, f64::round)
+}
+
+pub fn is_int(mut tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 1 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        });
+    }
+
+    let is_int = matches!(tokens.pop_front(), Some(Token::Number(n)) if n.is_integer());
+
+    Ok(Token::Bool(is_int))
+}
+
+pub fn is_float(mut tokens: List, _: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() != 1 {
+        return Err(RuntimeError::InvalidArgCount {
+            expected: 1,
+            got: tokens.len(),
+        });
+    }
+
+    let is_float = matches!(tokens.pop_front(), Some(Token::Number(Number::Float(_))));
+
+    Ok(Token::Bool(is_float))
+}
+
+pub fn sqrt(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    float(tokens, scope, f64::sqrt)
+}
+
+pub fn exp(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    float(tokens, scope, f64::exp)
+}
+
+/// `(log x)` is the natural logarithm, `(log x base)` takes any base.
+pub fn log(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() == 2 {
+        let [x, base] = numbers(tokens, scope)?;
+        return Ok(Token::Number(Number::Float(x.to_f64().log(base.to_f64()))));
+    }
+
+    float(tokens, scope, f64::ln)
+}
+
+pub fn sin(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    float(tokens, scope, f64::sin)
+}
+
+pub fn cos(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    float(tokens, scope, f64::cos)
+}
+
+pub fn tan(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    float(tokens, scope, f64::tan)
+}
+
+pub fn asin(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    float(tokens, scope, f64::asin)
+}
+
+pub fn acos(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    float(tokens, scope, f64::acos)
+}
+
+/// `(atan y x)` gives the angle of the point `(x, y)`, taking the quadrant
+/// into account.
+pub fn atan(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    if tokens.len() == 2 {
+        let [y, x] = numbers(tokens, scope)?;
+        return Ok(Token::Number(Number::Float(y.to_f64().atan2(x.to_f64()))));
+    }
+
+    float(tokens, scope, f64::atan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Interpreter, stdlib::eval};
+
+    #[test]
+    fn functions() {
+        let cases = [
+            ("(abs (sub 0 3))", "3"),
+            ("(abs -2.5)", "2.5"),
+            ("(min 3 1.5 2)", "1.5"),
+            ("(max 3 1 2)", "3"),
+            ("(clamp 15 0 10)", "10"),
+            ("(floor -2.5)", "-3"),
+            ("(ceil 2.1)", "3"),
+            ("(round 2.5)", "3"),
+            ("(floor 1e20)", "100000000000000000000"),
+            ("(sqrt 16)", "4.0"),
+            ("(pow 2 0.5)", "1.4142135623730951"),
+            ("(log 8 2)", "3.0"),
+            ("(round (mul 1000 (atan 1 1)))", "785"),
+            ("(gcd 12 18 30)", "6"),
+            ("(lcm 4 6)", "12"),
+            ("(lt 3.14 pi 3.15)", "true"),
+            ("(do (var pi 3) pi)", "3"),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(eval(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        let cases = [
+            (
+                "(do (func factorial [n] (if (lt n 2) 1 (mul n (factorial (sub n 1))))) (factorial 25))",
+                "15511210043330985984000000",
+            ),
+            ("(div 7 2)", "3.5"),
+            ("(quot -7 2)", "-3"),
+            ("(rem -7 2)", "-1"),
+            ("(mod -7 2)", "1"),
+            ("(pow 2 64)", "18446744073709551616"),
+            ("(pow 2 -1)", "0.5"),
+            ("(add 1 0.5)", "1.5"),
+            ("(int 2.9)", "2"),
+            ("(float 3)", "3.0"),
+            ("(concat (int? 3) (float? 3))", "truefalse"),
+            ("(eq 1 1.0)", "true"),
+            ("(add 0x10 1_000_000)", "1000016"),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(eval(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert!(eval("(div 1 0)").contains("division by zero"));
+        assert!(eval("(mod 1 0)").contains("invalid value: division by zero"));
+        assert!(eval("(mod 1.5 0)").contains("division by zero"));
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_division_by_zero(DivisionByZero::Infinity);
+
+        let value = interpreter
+            .eval_str("(concat (div 1 0) \" \" (div -1 0.0) \" \" (div 0 0))")
+            .unwrap();
+
+        assert_eq!(value, Token::String("inf -inf NaN".to_string()));
+        assert!(interpreter.eval_str("(quot 1 0)").is_err());
+    }
+}
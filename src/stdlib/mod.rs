@@ -0,0 +1,51 @@
+//! Libraries of builtins grouped by the values they work on.
+
+use crate::{
+    errors::RuntimeError,
+    host::Arity,
+    scope::Scope,
+    token::{List, Token},
+};
+
+pub mod list;
+pub mod math;
+pub mod string;
+
+/// Checks the number of arguments and hands them out in order.
+fn arguments(
+    tokens: List,
+    min: usize,
+    max: usize,
+) -> Result<impl Iterator<Item = Token>, RuntimeError> {
+    Arity {
+        min,
+        max: Some(max),
+    }
+    .check(tokens.len())?;
+
+    Ok(tokens.0.into_iter())
+}
+
+/// A position or a count, it can't be negative.
+fn index(token: Token, scope: &Scope) -> Result<usize, RuntimeError> {
+    let value: i64 = token.extract(scope)?;
+
+    usize::try_from(value)
+        .map_err(|_| RuntimeError::InvalidValue(format!("{} can't be negative", value)))
+}
+
+/// Runs `source` with both engines, which must agree, and shows the value of
+/// its last expression or the error.
+#[cfg(test)]
+pub(crate) fn eval(source: &str) -> String {
+    use crate::{Engine, Interpreter};
+
+    let [tree, vm] = [Engine::Tree, Engine::Vm].map(|engine| {
+        Interpreter::with_engine(engine)
+            .eval_str(source)
+            .map_or_else(|e| e.to_string(), |value| value.to_string())
+    });
+
+    assert_eq!(tree, vm, "{}", source);
+    tree
+}
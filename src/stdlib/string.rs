@@ -0,0 +1,319 @@
+use crate::{
+    builtins::BuiltinFunc,
+    errors::RuntimeError,
+    scope::Scope,
+    token::{List, Number, Token},
+};
+
+use super::{arguments, index};
+
+/// String builtins. They count and index characters, not bytes, and any
+/// value passed where a string is expected is converted to one. `len` and
+/// `contains` are shared with lists, see [`super::list`].
+pub const FUNCTIONS: &[(&str, BuiltinFunc)] = &[
+    ("substr", substr),
+    ("index-of", index_of),
+    ("split", split),
+    ("join", join),
+    ("trim", trim),
+    ("upper", upper),
+    ("lower", lower),
+    ("replace", replace),
+    ("starts-with", starts_with),
+    ("ends-with", ends_with),
+    ("chars", chars),
+    ("repeat", repeat),
+    ("pad-left", pad_left),
+    ("pad-right", pad_right),
+    // Conversions
+    ("str->number", to_number),
+    ("number->str", to_str),
+];
+
+/// Longest string `repeat` and the pad functions build, in bytes
+const MAX_LEN: usize = 1 << 30;
+
+/// Bytes taken by `times` copies of `unit` bytes, refusing what exceeds
+/// [`MAX_LEN`] rather than failing to allocate it.
+fn built_len(unit: usize, times: usize) -> Result<usize, RuntimeError> {
+    unit.checked_mul(times)
+        .filter(|&len| len <= MAX_LEN)
+        .ok_or_else(|| {
+            RuntimeError::InvalidValue(format!("strings can't be longer than {} bytes", MAX_LEN))
+        })
+}
+
+fn string(token: Token, scope: &Scope) -> Result<String, RuntimeError> {
+    token.extract(scope)
+}
+
+/// Byte offset of the character at `index` in `s`, the length of `s` for the
+/// position just past its end.
+fn offset(s: &str, index: usize) -> Result<usize, RuntimeError> {
+    s.char_indices()
+        .map(|(offset, _)| offset)
+        .chain([s.len()])
+        .nth(index)
+        .ok_or_else(|| {
+            RuntimeError::InvalidValue(format!(
+                "index {} is out of range for a string of {} characters",
+                index,
+                s.chars().count()
+            ))
+        })
+}
+
+/// Applies a function to a single string.
+fn unary(tokens: List, scope: &Scope, op: fn(&str) -> Token) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, 1)?;
+    Ok(op(&string(args.next().unwrap(), scope)?))
+}
+
+/// Applies a function to two strings.
+fn binary(tokens: List, scope: &Scope, op: fn(&str, &str) -> Token) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 2, 2)?;
+    let a = string(args.next().unwrap(), scope)?;
+    let b = string(args.next().unwrap(), scope)?;
+
+    Ok(op(&a, &b))
+}
+
+fn strings<'a>(items: impl Iterator<Item = &'a str>) -> Token {
+    Token::List(List::from_iterable(
+        items.map(|item| Token::String(item.to_string())),
+    ))
+}
+
+/// `(substr s start [end])` takes the characters from `start` up to, but not
+/// including, `end` or the end of the string.
+pub fn substr(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 2, 3)?;
+    let s = string(args.next().unwrap(), scope)?;
+    let start = index(args.next().unwrap(), scope)?;
+
+    let end = match args.next() {
+        Some(end) => index(end, scope)?,
+        None => s.chars().count(),
+    };
+
+    if start > end {
+        return Err(RuntimeError::InvalidValue(format!(
+            "substring start {} is after its end {}",
+            start, end
+        )));
+    }
+
+    Ok(Token::String(
+        s[offset(&s, start)?..offset(&s, end)?].to_string(),
+    ))
+}
+
+/// `(index-of s part [from])` is the position of the first `part` in `s` at
+/// or after `from`, `-1` if there's none.
+pub fn index_of(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 2, 3)?;
+    let s = string(args.next().unwrap(), scope)?;
+    let part = string(args.next().unwrap(), scope)?;
+
+    let from = match args.next() {
+        Some(from) => index(from, scope)?,
+        None => 0,
+    };
+
+    let start = offset(&s, from)?;
+
+    Ok(match s[start..].find(&part) {
+        Some(found) => Token::Number(Number::Int(
+            (from + s[start..start + found].chars().count()) as i64,
+        )),
+        None => Token::Number(Number::Int(-1)),
+    })
+}
+
+/// `(split s separator)` returns the list of the parts between separators,
+/// an empty separator splits the string into characters.
+pub fn split(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    binary(tokens, scope, |s, separator| match separator {
+        "" => characters(s),
+        _ => strings(s.split(separator)),
+    })
+}
+
+/// `(join list [separator])` concatenates the items of a list.
+pub fn join(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, 2)?;
+    let items: List = args.next().unwrap().extract(scope)?;
+
+    let separator = match args.next() {
+        Some(separator) => string(separator, scope)?,
+        None => String::new(),
+    };
+
+    let parts: Vec<String> = items.iter().map(Token::to_string).collect();
+
+    Ok(Token::String(parts.join(&separator)))
+}
+
+pub fn trim(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    unary(tokens, scope, |s| Token::String(s.trim().to_string()))
+}
+
+pub fn upper(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    unary(tokens, scope, |s| Token::String(s.to_uppercase()))
+}
+
+pub fn lower(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    unary(tokens, scope, |s| Token::String(s.to_lowercase()))
+}
+
+/// `(replace s from to)` replaces every `from` in `s`.
+pub fn replace(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 3, 3)?;
+    let s = string(args.next().unwrap(), scope)?;
+    let from = string(args.next().unwrap(), scope)?;
+    let to = string(args.next().unwrap(), scope)?;
+
+    if from.is_empty() {
+        return Err(RuntimeError::InvalidValue(
+            "can't replace an empty string".to_string(),
+        ));
+    }
+
+    Ok(Token::String(s.replace(&from, &to)))
+}
+
+pub fn starts_with(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    binary(tokens, scope, |s, prefix| {
+        Token::Bool(s.starts_with(prefix))
+    })
+}
+
+pub fn ends_with(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    binary(tokens, scope, |s, suffix| Token::Bool(s.ends_with(suffix)))
+}
+
+fn characters(s: &str) -> Token {
+    Token::List(List::from_iterable(
+        s.chars().map(|ch| Token::String(ch.to_string())),
+    ))
+}
+
+/// The list of the characters of a string, each one a string of its own.
+pub fn chars(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    unary(tokens, scope, characters)
+}
+
+pub fn repeat(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 2, 2)?;
+    let s = string(args.next().unwrap(), scope)?;
+    let times = index(args.next().unwrap(), scope)?;
+
+    built_len(s.len(), times)?;
+    Ok(Token::String(s.repeat(times)))
+}
+
+/// Pads a string with `fill`, a space by default, up to `width` characters.
+fn pad(tokens: List, scope: &Scope, left: bool) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 2, 3)?;
+    let s = string(args.next().unwrap(), scope)?;
+    let width = index(args.next().unwrap(), scope)?;
+
+    let fill = match args.next() {
+        Some(fill) => string(fill, scope)?,
+        None => " ".to_string(),
+    };
+
+    let mut fill_chars = fill.chars();
+
+    let (Some(fill), None) = (fill_chars.next(), fill_chars.next()) else {
+        return Err(RuntimeError::InvalidValue(format!(
+            "padding `{}` isn't a single character",
+            fill
+        )));
+    };
+
+    let missing = width.saturating_sub(s.chars().count());
+    built_len(fill.len_utf8(), missing)?;
+
+    let padding: String = std::iter::repeat_n(fill, missing).collect();
+
+    Ok(Token::String(match left {
+        true => padding + &s,
+        false => s + &padding,
+    }))
+}
+
+/// `(pad-left s width [fill])`
+pub fn pad_left(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    pad(tokens, scope, true)
+}
+
+/// `(pad-right s width [fill])`
+pub fn pad_right(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    pad(tokens, scope, false)
+}
+
+/// Reads a number written the way the language writes them, surrounding
+/// whitespace is ignored. Returns `nil` when the string isn't a number.
+pub fn to_number(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    unary(tokens, scope, |s| {
+        Number::parse(s.trim()).map_or(Token::Nil, Token::Number)
+    })
+}
+
+pub fn to_str(tokens: List, scope: &Scope) -> Result<Token, RuntimeError> {
+    let mut args = arguments(tokens, 1, 1)?;
+    let n: Number = args.next().unwrap().extract(scope)?;
+
+    Ok(Token::String(n.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stdlib::eval;
+
+    #[test]
+    fn functions() {
+        let cases = [
+            ("(len \"héllo wörld\")", "11"),
+            ("(substr \"привет\" 2 4)", "ив"),
+            ("(substr \"привет\" 3)", "вет"),
+            ("(index-of \"añobaño\" \"ño\")", "1"),
+            ("(index-of \"añobaño\" \"ño\" 2)", "5"),
+            ("(index-of \"abc\" \"x\")", "-1"),
+            ("(join (split \"a,b,,c\" \",\") \"|\")", "a|b||c"),
+            ("(join (chars \"日本\") \" \")", "日 本"),
+            ("(join (split \"ab\" \"\") \"-\")", "a-b"),
+            ("(trim \"  x y \")", "x y"),
+            ("(upper \"straße\")", "STRASSE"),
+            ("(lower \"ÀB\")", "àb"),
+            ("(replace \"a-b-c\" \"-\" \"+\")", "a+b+c"),
+            ("(starts-with \"prefix\" \"pre\")", "true"),
+            ("(ends-with \"prefix\" \"pre\")", "false"),
+            ("(repeat \"ab\" 3)", "ababab"),
+            ("(pad-left 7 3 \"0\")", "007"),
+            ("(pad-right \"ü\" 3 \"·\")", "ü··"),
+            ("(pad-left \"long\" 2)", "long"),
+            ("(add (str->number \" 0x10 \") 1)", "17"),
+            ("(str->number \"1.5e1\")", "15.0"),
+            ("(str->number \"12abc\")", "nil"),
+            ("(concat (number->str 2.0) \"!\")", "2.0!"),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(eval(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert!(eval("(substr \"abc\" 2 5)").contains("out of range"));
+        assert!(eval("(substr \"abc\" 2 1)").contains("after its end"));
+        assert!(eval("(repeat \"a\" -1)").contains("negative"));
+        assert!(eval("(repeat \"ab\" 9223372036854775807)").contains("can't be longer"));
+        assert!(eval("(pad-left \"a\" 9223372036854775807)").contains("can't be longer"));
+        assert!(eval("(pad-right \"a\" 9223372036854775807 \"·\")").contains("can't be longer"));
+        assert!(eval("(pad-left \"a\" 3 \"xy\")").contains("single character"));
+        assert!(eval("(number->str \"1\")").contains("number"));
+    }
+}
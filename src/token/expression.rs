@@ -1,83 +1,257 @@
-use std::fmt::Display;
+use std::{cell::Cell, cmp::Ordering, fmt::Display};
 
-use crate::{builtins::BUILTIN_FUNCTIONS, errors::RuntimeError, scope::get_function};
+use crate::{
+    builtins::SPECIAL_FORMS,
+    errors::{CallFrame, RuntimeError},
+    scope::{Function, Scope},
+    source::Span,
+};
 
 use super::{Identifier, List, Token};
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug)]
 pub struct Expression {
     pub fid: Option<Identifier>,
     pub args: List,
-    pub line: usize,
-    pub pos: usize,
+    /// Location of the whole expression
+    pub span: Span,
+    /// Locations of the elements as written in the source, head included
+    pub spans: Vec<Span>,
 }
 
 impl Expression {
-    pub fn from_iterable<T>(iterable: T, line: usize, pos: usize) -> Self
+    pub fn from_iterable<T>(iterable: T, span: Span) -> Self
     where
         T: IntoIterator<Item = Token>,
     {
-        let mut iter = iterable.into_iter();
-        let maybe_fid = iter.next();
-        let fid;
-
-        if maybe_fid.is_some() {
-            fid = Some(maybe_fid.unwrap().extract::<Identifier>(None).unwrap());
-        } else {
-            fid = None;
-        }
+        let mut args = List::from_iterable(iterable);
+
+        // `((lambda [x] x) 1)` and friends are rewritten into `(call ...)`
+        let fid = match args.pop_front() {
+            None => None,
+            Some(Token::Identifier(id)) => Some(id),
+            Some(head) => {
+                args.push_front(head);
+                Some(Identifier("call".to_string()))
+            }
+        };
 
         Self {
             fid,
-            args: List::from_iterable(iter),
-            line,
-            pos,
+            args,
+            span,
+            spans: Vec::new(),
+        }
+    }
+
+    pub fn execute(&self, scope: &Scope) -> Result<Token, RuntimeError> {
+        match self.step(scope) {
+            Ok(tail) => run(tail, Some(self.span.clone())),
+            Err(error) => Err(error.located(self.span.clone())),
         }
     }
 
-    pub fn execute(&self, maybe_prefix: Option<String>) -> Result<Token, RuntimeError> {
-        let Some(func_id) = self.fid.clone() else {
-            return Ok(Token::Nil);
+    /// Evaluates the expression up to its tail position. Special forms get
+    /// their arguments as written, macros get them as written too and their
+    /// expansion is evaluated in place of the call. User functions and
+    /// builtins get them evaluated from left to right, a function bound to
+    /// the name of a builtin shadows it.
+    fn step(&self, scope: &Scope) -> Result<Tail, RuntimeError> {
+        let Some(func_id) = &self.fid else {
+            return Ok(Tail::Value(Token::Nil));
         };
 
-        let args = self.args.clone();
+        if let Some(form) = SPECIAL_FORMS.get(func_id.as_str()) {
+            return form(self.args.clone(), scope);
+        }
 
-        match BUILTIN_FUNCTIONS.get(func_id.0.as_str()) {
-            Some(func) => func(args, maybe_prefix),
+        if let Some(m) = scope.get_macro(func_id) {
+            let expanded = m.apply(self.args.0.clone().into())?;
+            return Ok(Tail::Eval(expanded, scope.clone()));
+        }
 
-            None => match get_function(&func_id.0) {
-                Some(f) => f.call(func_id.0, args),
-                None => Err(RuntimeError::UndefinedFunction(func_id.to_string())),
-            },
+        if let Some(f) = scope.get_function(func_id) {
+            return Ok(Tail::Call(f, evaluate(&self.args, scope)?));
         }
+
+        match scope.get_builtin(func_id) {
+            Some(builtin) => {
+                let values = evaluate(&self.args, scope)?;
+                builtin
+                    .call(List::from_iterable(values), scope)
+                    .map(Tail::Value)
+            }
+            None => Err(RuntimeError::UndefinedFunction(func_id.to_string())),
+        }
+    }
+}
+
+/// Evaluates `args` from left to right.
+pub fn evaluate(args: &List, scope: &Scope) -> Result<Vec<Token>, RuntimeError> {
+    let mut values = Vec::with_capacity(args.len());
+
+    for token in args.iter() {
+        values.push(token.clone().into_value(scope)?);
+    }
+
+    Ok(values)
+}
+
+/// What is left to do after a special form ran.
+pub enum Tail {
+    Value(Token),
+    /// Evaluate the token in the scope in place of the form
+    Eval(Token, Scope),
+    /// Apply the function to already evaluated arguments
+    Call(Function, Vec<Token>),
+}
+
+/// How deep [`run`] may nest before evaluation gives up with
+/// [`RuntimeError::StackOverflow`]. Calls that are not in tail position and
+/// nested expressions take one level each; [`STACK_SIZE`] bytes of host stack
+/// are enough to reach it.
+pub const MAX_DEPTH: usize = 10_000;
+
+/// Host stack a thread needs to evaluate up to [`MAX_DEPTH`] nested levels.
+pub const STACK_SIZE: usize = 1 << 30;
+
+thread_local! {
+    /// Levels of [`run`] currently on the stack of this thread
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// One level of [`run`], given back when dropped.
+struct Level;
+
+impl Level {
+    fn enter() -> Result<Self, RuntimeError> {
+        DEPTH.with(|depth| {
+            if depth.get() >= MAX_DEPTH {
+                return Err(RuntimeError::StackOverflow { limit: MAX_DEPTH });
+            }
+
+            depth.set(depth.get() + 1);
+            Ok(Self)
+        })
     }
 }
 
+impl Drop for Level {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// Drives evaluation until a value is produced. Tail calls replace the running
+/// function instead of growing the Rust stack, so a chain of tail calls runs
+/// in constant space and only the latest of them appears in a backtrace.
+pub fn run(mut tail: Tail, mut call_site: Option<Span>) -> Result<Token, RuntimeError> {
+    let _level = Level::enter().map_err(|error| error.located_at(call_site.clone()))?;
+    let mut frame: Option<CallFrame> = None;
+
+    loop {
+        let (expression, scope) = match tail {
+            Tail::Value(value) => return Ok(value),
+
+            Tail::Eval(Token::Expression(e), scope) => (e, scope),
+
+            Tail::Eval(token, scope) => {
+                return token.into_value(&scope).map_err(|e| leave_frame(e, frame));
+            }
+
+            Tail::Call(function, args) => {
+                let entered = match function.builtin() {
+                    // Runs in the scope the builtin was taken from
+                    Some(builtin) => builtin
+                        .call(List::from_iterable(args), &function.scope())
+                        .map(Tail::Value),
+                    None => function.enter(args).map(|(body, scope, mut entered)| {
+                        entered.call_site = call_site.clone();
+                        frame = Some(entered);
+                        Tail::Eval(Token::Expression(body), scope)
+                    }),
+                };
+
+                tail = match entered {
+                    Ok(tail) => tail,
+                    Err(error) => {
+                        let error = match call_site {
+                            Some(span) => error.located(span),
+                            None => error,
+                        };
+
+                        return Err(leave_frame(error, frame));
+                    }
+                };
+
+                continue;
+            }
+        };
+
+        call_site = Some(expression.span.clone());
+
+        tail = match expression.step(&scope) {
+            Ok(tail) => tail,
+            Err(error) => return Err(leave_frame(error.located(expression.span), frame)),
+        };
+    }
+}
+
+fn leave_frame(error: RuntimeError, frame: Option<CallFrame>) -> RuntimeError {
+    match frame {
+        Some(frame) => error.unwound(frame),
+        None => error,
+    }
+}
+
+/// Spans don't take part in comparisons: the same code written in two places
+/// is equal.
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.fid == other.fid && self.args == other.args
+    }
+}
+
+impl PartialOrd for Expression {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (&self.fid, &self.args).partial_cmp(&(&other.fid, &other.args))
+    }
+}
+
+/// Writes the expression as source the parser reads back, see
+/// [`Token::repr`].
 impl Display for Expression {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "expression...")
+        let items: Vec<String> = self
+            .fid
+            .iter()
+            .map(|id| id.0.clone())
+            .chain(self.args.iter().map(Token::repr))
+            .collect();
+
+        write!(f, "({})", items.join(" "))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{parser::Parser, source::line_col, token::Number};
 
     #[test]
     fn sum_of_two() {
         let expression = Expression::from_iterable(
             [
                 Token::Identifier(Identifier("add".to_string())),
-                Token::Number(1.0),
-                Token::Number(1.0),
+                Token::Number(Number::Int(1)),
+                Token::Number(Number::Int(1)),
             ],
-            0,
-            0,
+            Span::default(),
         );
 
-        let expected = Token::Number(2.0);
+        let expected = Token::Number(Number::Int(2));
 
-        assert_eq!(expression.execute(None).unwrap(), expected);
+        assert_eq!(expression.execute(&Scope::new()).unwrap(), expected);
     }
 
     #[test]
@@ -88,8 +262,7 @@ mod tests {
                 Token::Identifier(Identifier("name".to_string())),
                 Token::String("Denis".to_string()),
             ],
-            0,
-            0,
+            Span::default(),
         );
 
         let concat = Expression::from_iterable(
@@ -98,17 +271,18 @@ mod tests {
                 Token::String("Hello ".to_string()),
                 Token::Identifier(Identifier("name".to_string())),
             ],
-            0,
-            0,
+            Span::default(),
         );
 
+        let scope = Scope::new();
+
         assert_eq!(
-            set_variable.execute(None).unwrap(),
+            set_variable.execute(&scope).unwrap(),
             Token::String("Denis".to_string())
         );
 
         assert_eq!(
-            concat.execute(None).unwrap(),
+            concat.execute(&scope).unwrap(),
             Token::String("Hello Denis".to_string())
         );
     }
@@ -121,25 +295,229 @@ mod tests {
                 Token::Expression(Expression::from_iterable(
                     [
                         Token::Identifier(Identifier("add".to_string())),
-                        Token::Number(1.0),
+                        Token::Number(Number::Int(1)),
                     ],
-                    0,
-                    0,
+                    Span::default(),
                 )),
                 Token::Expression(Expression::from_iterable(
                     [
                         Token::Identifier(Identifier("add".to_string())),
-                        Token::Number(1.0),
+                        Token::Number(Number::Int(1)),
                     ],
-                    0,
-                    0,
+                    Span::default(),
                 )),
             ],
-            0,
-            0,
+            Span::default(),
+        );
+
+        assert_eq!(
+            expression.execute(&Scope::new()).unwrap(),
+            Token::Number(Number::Int(2))
+        );
+    }
+
+    fn run(source: &str) -> Token {
+        let scope = Scope::new();
+        let mut result = Token::Nil;
+
+        for e in Parser::with_name("<test>", source)
+            .parse_expressions()
+            .unwrap()
+        {
+            result = e.execute(&scope).unwrap();
+        }
+
+        result
+    }
+
+    #[test]
+    fn recursion() {
+        let source = "
+            (func fib [n]
+                (if (lt n 2)
+                    n
+                    (add (fib (sub n 1)) (fib (sub n 2)))))
+            (fib 10)
+        ";
+
+        assert_eq!(run(source), Token::Number(Number::Int(55)));
+    }
+
+    #[test]
+    fn arguments_shadow_globals() {
+        let source = "
+            (var n 100)
+            (func double [n] (mul n 2))
+            (add (double 4) n)
+        ";
+
+        assert_eq!(run(source), Token::Number(Number::Int(108)));
+    }
+
+    #[test]
+    fn locals_do_not_leak() {
+        let source = "
+            (func setLocal [x] (var y x))
+            (setLocal 5)
+            (eq y nil)
+        ";
+
+        assert_eq!(run(source), Token::Bool(true));
+    }
+
+    #[test]
+    fn closures_capture_scope() {
+        let source = "
+            (func adder [n] (lambda [x] (add x n)))
+            (var addFive (adder 5))
+            (var n 100)
+            (addFive 10)
+        ";
+
+        assert_eq!(run(source), Token::Number(Number::Int(15)));
+    }
+
+    #[test]
+    fn functions_as_values() {
+        let source = "
+            (func twice [f x] (f (f x)))
+            (func inc [x] (add x 1))
+            (var r1 (twice inc 1))
+            (var r2 (call twice (fn [x] (mul x 3)) 1))
+            (var r3 ((lambda [a b] (sub a b)) 10 4))
+            (add r1 r2 r3)
+        ";
+
+        assert_eq!(run(source), Token::Number(Number::Int(18)));
+    }
+
+    #[test]
+    fn arguments_run_in_order() {
+        let source = "
+            (var order \"\")
+            (var a 1)
+            (concat
+                (eq a (sub 2 1))
+                (do (var order (concat order \"x\")) \"a\")
+                (do (var order (concat order \"y\")) \"b\")
+                order)
+        ";
+
+        assert_eq!(run(source), Token::String("trueabxy".to_string()));
+    }
+
+    #[test]
+    fn untaken_branches_do_not_run() {
+        assert_eq!(run("(if false (missing) 1)"), Token::Number(Number::Int(1)));
+
+        let error = Parser::with_name("<test>", "(if false (missing) (typeof missing_too))")
+            .parse_expressions()
+            .unwrap()[0]
+            .execute(&Scope::new())
+            .unwrap_err();
+
+        assert_eq!(
+            error.kind(),
+            &RuntimeError::UndefinedFunction("missing_too".to_string())
         );
+    }
+
+    #[test]
+    fn backtrace() {
+        let source =
+            "(func inner [x] (add x \"b\"))\n(func outer [y] (add 1 (inner y)))\n(outer 1)";
+
+        let scope = Scope::new();
+        let mut error = None;
+
+        for e in Parser::with_name("<test>", source)
+            .parse_expressions()
+            .unwrap()
+        {
+            error = e.execute(&scope).err();
+        }
+
+        let error = error.unwrap();
+        let trace: Vec<_> = error
+            .trace()
+            .iter()
+            .map(|frame| {
+                (
+                    frame.function.as_str(),
+                    frame.call_site.as_ref().and_then(line_col),
+                )
+            })
+            .collect();
+
+        assert_eq!(trace, [("inner", Some((2, 24))), ("outer", Some((3, 1)))]);
+        assert_eq!(
+            error.trace()[0].args,
+            [("x".to_string(), Token::Number(Number::Int(1)))]
+        );
+    }
+
+    #[test]
+    fn tail_calls() {
+        let source = "
+            (func countdown [n acc]
+                (if (eq n 0)
+                    acc
+                    (do
+                        (var next (sub n 1))
+                        (countdown next (add acc 1)))))
+            (countdown 20000 0)
+        ";
+
+        assert_eq!(run(source), Token::Number(Number::Int(20000)));
+    }
+
+    #[test]
+    fn mutual_tail_calls() {
+        let source = "
+            (func even [n] (if (eq n 0) true (call odd (sub n 1))))
+            (func odd [n] (if (eq n 0) false (even (sub n 1))))
+            (even 20001)
+        ";
+
+        assert_eq!(run(source), Token::Bool(false));
+    }
+
+    #[test]
+    fn runaway_recursion() {
+        let source = "
+            (func f [n] (add 1 (f (sub n 1))))
+            (func g [n] (if (eq n 0) 0 (add 1 (g (sub n 1)))))
+            (var caught (try (f 5000) (catch \"stack-overflow\" e (get e \"kind\"))))
+            (g 1000)
+            (f 5000)
+        ";
+
+        // Reaching the limit takes more than the stack of a test thread
+        let (error, caught) = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let scope = Scope::new();
+                let mut error = None;
+
+                for e in Parser::with_name("<test>", source)
+                    .parse_expressions()
+                    .unwrap()
+                {
+                    error = e.execute(&scope).err().map(|e| e.to_string());
+                }
+
+                (error, scope.get_variable("caught").to_string())
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(caught, "stack-overflow");
 
-        assert_eq!(expression.execute(None).unwrap(), Token::Number(2.0));
+        let error = error.unwrap();
+        assert!(error.starts_with("error: stack overflow"), "{}", error);
+        assert!(error.contains("0: f [n=-"), "{}", error);
+        assert!(error.contains("frames omitted"), "{}", error);
     }
 
     // #[test]
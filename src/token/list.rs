@@ -1,13 +1,18 @@
 use std::{
+    cmp::Ordering,
     collections::VecDeque,
     fmt::Display,
     ops::{Deref, DerefMut},
 };
 
+use crate::source::Span;
+
 use super::Token;
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
-pub struct List(pub VecDeque<Token>);
+/// Items of a list literal remember where they were written until the list
+/// is changed.
+#[derive(Clone, Debug)]
+pub struct List(pub VecDeque<Token>, Vec<Span>);
 
 impl List {
     // pub fn new() -> Self {
@@ -18,7 +23,24 @@ impl List {
     where
         T: IntoIterator<Item = Token>,
     {
-        List(iterable.into_iter().collect())
+        List(iterable.into_iter().collect(), Vec::new())
+    }
+
+    /// A list literal, `spans` are the locations of its items.
+    pub fn with_spans(items: Vec<(Token, Span)>) -> Self {
+        let (items, spans) = items.into_iter().unzip();
+        List(items, spans)
+    }
+
+    /// Where the item at `index` was written, if the list comes from source.
+    pub fn span(&self, index: usize) -> Option<&Span> {
+        self.1.get(index)
+    }
+
+    /// The items along with the locations they were written at.
+    pub fn into_spanned(self) -> impl Iterator<Item = (Token, Option<Span>)> {
+        let spans = self.1.into_iter().map(Some).chain(std::iter::repeat(None));
+        self.0.into_iter().zip(spans)
     }
 
     // pub fn get(&mut self) -> Token {
@@ -34,14 +56,32 @@ impl Deref for List {
     }
 }
 
+/// Changing the items forgets their locations.
 impl DerefMut for List {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        self.1.clear();
         &mut self.0
     }
 }
 
+/// Locations don't take part in comparisons.
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl PartialOrd for List {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+/// Items are written with [`Token::repr`], so strings in a list stay quoted.
 impl Display for List {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "list...")
+        let items: Vec<String> = self.iter().map(Token::repr).collect();
+
+        write!(f, "[{}]", items.join(" "))
     }
 }
@@ -1,40 +1,52 @@
 use std::fmt::Display;
 
-use crate::{errors::RuntimeError, extractor::Extractable, scope::get_variable};
+use crate::{
+    errors::RuntimeError,
+    extractor::Extractable,
+    scope::{Function, Scope},
+    utils::escape,
+};
 
-pub use expression::Expression;
+pub use expression::{Expression, MAX_DEPTH, STACK_SIZE, Tail, evaluate, run};
 pub use identifier::Identifier;
 pub use list::List;
+pub use number::Number;
+pub use object::Object;
 
 mod expression;
 mod identifier;
 mod list;
+mod number;
+mod object;
 
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub enum Token {
-    Number(f64),
+    Number(Number),
     String(String),
     Bool(bool),
     Nil,
 
     List(List),
-    // Object(Hashmap<Token, Token>),
+    Object(Object),
+    Function(Function),
     Identifier(Identifier),
     Expression(Expression),
 }
 
 impl Token {
-    pub fn into_value(self, maybe_prefix: Option<String>) -> Result<Token, RuntimeError> {
-        println!("into_value");
+    pub fn into_value(self, scope: &Scope) -> Result<Token, RuntimeError> {
         Ok(match self {
-            Token::Identifier(id) => {
-                let variable_name = match maybe_prefix {
-                    Some(prefix) => prefix + &id.0,
-                    None => id.0,
-                };
-                get_variable(&variable_name)
+            Token::Identifier(id) => scope.get_variable(&id),
+            Token::Expression(e) => e.execute(scope)?,
+            Token::Object(object) => {
+                let mut evaluated = Object::default();
+
+                for (key, value) in object.0 {
+                    evaluated.insert(key, value.into_value(scope)?);
+                }
+
+                Token::Object(evaluated)
             }
-            Token::Expression(e) => e.execute(maybe_prefix)?,
             _ => self,
         })
     }
@@ -46,23 +58,103 @@ impl Token {
             Token::Bool(_) => "bool",
             Token::Nil => "nil",
             Token::List(_) => "list",
-            // ULispType::Object => "object",
+            Token::Object(_) => "object",
+            Token::Function(_) => "function",
             Token::Identifier(_) => "identifier",
             Token::Expression(_) => "expression",
         }
         .to_string()
     }
 
-    pub fn extract<T: Extractable>(self, maybe_prefix: Option<String>) -> Result<T, RuntimeError> {
-        // if matches!(self, Token::Identifier(_) | Token::Expression(_)) {
-        //     self = self.into_value()?;
-        // }
+    pub fn extract<T: Extractable>(self, scope: &Scope) -> Result<T, RuntimeError> {
+        T::extract(self, scope)
+    }
+
+    /// Writes the value the way it's written in source: unlike `Display`,
+    /// strings are quoted and escaped. Data read back by the parser gives an
+    /// equal value, functions can't be read back.
+    pub fn repr(&self) -> String {
+        match self {
+            Self::String(v) => format!("\"{}\"", escape(v)),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl From<Number> for Token {
+    fn from(value: Number) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<i64> for Token {
+    fn from(value: i64) -> Self {
+        Self::Number(Number::Int(value))
+    }
+}
+
+impl From<f64> for Token {
+    fn from(value: f64) -> Self {
+        Self::Number(Number::Float(value))
+    }
+}
+
+impl From<bool> for Token {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<String> for Token {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<&str> for Token {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<()> for Token {
+    fn from(_: ()) -> Self {
+        Self::Nil
+    }
+}
+
+impl From<List> for Token {
+    fn from(value: List) -> Self {
+        Self::List(value)
+    }
+}
+
+impl From<Object> for Token {
+    fn from(value: Object) -> Self {
+        Self::Object(value)
+    }
+}
+
+impl From<Function> for Token {
+    fn from(value: Function) -> Self {
+        Self::Function(value)
+    }
+}
+
+impl<T: Into<Token>> From<Vec<T>> for Token {
+    fn from(value: Vec<T>) -> Self {
+        Self::List(List::from_iterable(value.into_iter().map(Into::into)))
+    }
+}
 
-        println!("token ext {}", self);
-        T::extract(self, maybe_prefix)
+impl<T: Into<Token>> From<Option<T>> for Token {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Nil, Into::into)
     }
 }
 
+/// Strings are written as they are, as `print` shows them. Lists, objects
+/// and expressions write what they hold with [`Token::repr`].
 impl Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let output = match self {
@@ -72,6 +164,8 @@ impl Display for Token {
             Self::Bool(v) => v.to_string(),
             Self::Nil => "nil".to_string(),
             Self::List(list) => list.to_string(),
+            Self::Object(object) => object.to_string(),
+            Self::Function(func) => func.to_string(),
             Self::Expression(exp) => exp.to_string(),
         };
 
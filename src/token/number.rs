@@ -0,0 +1,340 @@
+use std::{cmp::Ordering, fmt::Display};
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+use crate::errors::RuntimeError;
+
+/// A number of the language. Integers are exact and grow into bignums instead
+/// of overflowing, floats only appear when written as such or produced by an
+/// operation on a float.
+#[derive(Clone, Debug)]
+pub enum Number {
+    Int(i64),
+    /// Only holds values that don't fit into an `Int`
+    Big(BigInt),
+    Float(f64),
+}
+
+type IntOp = fn(i64, i64) -> Option<i64>;
+type BigOp = fn(&BigInt, &BigInt) -> BigInt;
+type FloatOp = fn(f64, f64) -> f64;
+
+impl Number {
+    /// Wraps a bignum, shrinking it to an `Int` when it fits.
+    pub fn big(value: BigInt) -> Self {
+        match value.to_i64() {
+            Some(value) => Self::Int(value),
+            None => Self::Big(value),
+        }
+    }
+
+    /// Parses a numeric literal: decimal integers and floats with an optional
+    /// exponent, `0x`/`0b`/`0o` integers, `_` may separate digits.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (negative, unsigned) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+
+        if unsigned.starts_with('_') || unsigned.ends_with('_') || unsigned.contains("__") {
+            return None;
+        }
+
+        let radix = match unsigned.get(..2).map(str::to_ascii_lowercase).as_deref() {
+            Some("0x") => 16,
+            Some("0b") => 2,
+            Some("0o") => 8,
+            _ => 10,
+        };
+
+        let digits: String = match radix {
+            10 => unsigned,
+            _ => &unsigned[2..],
+        }
+        .chars()
+        .filter(|&ch| ch != '_')
+        .collect();
+
+        if digits.is_empty() || digits.starts_with(['+', '-']) {
+            return None;
+        }
+
+        let sign = if negative { "-" } else { "" };
+
+        if radix == 10 && digits.contains(['.', 'e', 'E']) {
+            // Rust also accepts `inf`, `nan` and a bare `.5` or `5.`
+            let valid = digits
+                .chars()
+                .all(|ch| ch.is_ascii_digit() || "eE.+-".contains(ch))
+                && digits.starts_with(|ch: char| ch.is_ascii_digit())
+                && !digits.contains(".e")
+                && !digits.contains(".E")
+                && !digits.ends_with('.');
+
+            return match valid {
+                true => format!("{}{}", sign, digits).parse().ok().map(Self::Float),
+                false => None,
+            };
+        }
+
+        BigInt::parse_bytes(format!("{}{}", sign, digits).as_bytes(), radix).map(Self::big)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Self::Int(value) => *value as f64,
+            Self::Big(value) => value.to_f64().unwrap_or(f64::NAN),
+            Self::Float(value) => *value,
+        }
+    }
+
+    /// The value of an integer as a bignum, `None` for floats.
+    pub fn to_big(&self) -> Option<BigInt> {
+        match self {
+            Self::Int(value) => Some(BigInt::from(*value)),
+            Self::Big(value) => Some(value.clone()),
+            Self::Float(_) => None,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        !matches!(self, Self::Float(_))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Self::Int(value) => *value == 0,
+            Self::Big(value) => value.is_zero(),
+            Self::Float(value) => *value == 0.0,
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Self::Int(value) => *value < 0,
+            Self::Big(value) => value.is_negative(),
+            Self::Float(value) => *value < 0.0,
+        }
+    }
+
+    /// Applies an operation in the narrowest representation both operands
+    /// fit: `i64` while it doesn't overflow, then bignums, and floats as soon
+    /// as one of the operands is a float.
+    fn combine(&self, other: &Self, int: IntOp, big: BigOp, float: FloatOp) -> Self {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => match int(*a, *b) {
+                Some(value) => Self::Int(value),
+                None => Self::big(big(&BigInt::from(*a), &BigInt::from(*b))),
+            },
+            (Self::Float(_), _) | (_, Self::Float(_)) => {
+                Self::Float(float(self.to_f64(), other.to_f64()))
+            }
+            _ => Self::big(big(&self.to_big().unwrap(), &other.to_big().unwrap())),
+        }
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        self.combine(other, i64::checked_add, |a, b| a + b, |a, b| a + b)
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        self.combine(other, i64::checked_sub, |a, b| a - b, |a, b| a - b)
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        self.combine(other, i64::checked_mul, |a, b| a * b, |a, b| a * b)
+    }
+
+    /// True division, the result is always a float.
+    pub fn div(&self, other: &Self) -> Self {
+        Self::Float(self.to_f64() / other.to_f64())
+    }
+
+    fn check_divisor(&self, other: &Self) -> Result<(), RuntimeError> {
+        match self.is_integer() && other.is_integer() && other.is_zero() {
+            true => Err(RuntimeError::InvalidValue("division by zero".to_string())),
+            false => Ok(()),
+        }
+    }
+
+    /// Division rounding towards zero.
+    pub fn quot(&self, other: &Self) -> Result<Self, RuntimeError> {
+        self.check_divisor(other)?;
+
+        Ok(self.combine(
+            other,
+            i64::checked_div,
+            |a, b| a / b,
+            |a, b| (a / b).trunc(),
+        ))
+    }
+
+    /// Remainder of [`Number::quot`], it has the sign of the dividend.
+    pub fn rem(&self, other: &Self) -> Result<Self, RuntimeError> {
+        self.check_divisor(other)?;
+
+        Ok(self.combine(other, i64::checked_rem, |a, b| a % b, |a, b| a % b))
+    }
+
+    /// Remainder of the division rounding down, it has the sign of the
+    /// divisor.
+    pub fn modulo(&self, other: &Self) -> Result<Self, RuntimeError> {
+        self.check_divisor(other)?;
+
+        Ok(self.combine(
+            other,
+            |a, b| {
+                a.checked_rem_euclid(b)
+                    .map(|r| if r != 0 && b < 0 { r + b } else { r })
+            },
+            |a, b| a.mod_floor(b),
+            |a, b| a - b * (a / b).floor(),
+        ))
+    }
+
+    /// Exact for integers raised to a non-negative integer power.
+    pub fn pow(&self, other: &Self) -> Self {
+        let exponent = match other {
+            Self::Int(exponent) if self.is_integer() => u32::try_from(*exponent).ok(),
+            _ => None,
+        };
+
+        match (self, exponent) {
+            (Self::Int(base), Some(exponent)) => match base.checked_pow(exponent) {
+                Some(value) => Self::Int(value),
+                None => Self::big(BigInt::from(*base).pow(exponent)),
+            },
+            (Self::Big(base), Some(exponent)) => Self::big(base.pow(exponent)),
+            _ => Self::Float(self.to_f64().powf(other.to_f64())),
+        }
+    }
+
+    /// Whether a range going from `self` by `step` hasn't reached `end` yet.
+    pub fn in_range(&self, end: &Self, step: &Self) -> bool {
+        match step.is_negative() {
+            true => self > end,
+            false => self < end,
+        }
+    }
+
+    pub fn neg(&self) -> Self {
+        Self::Int(0).sub(self)
+    }
+}
+
+impl From<i64> for Number {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<f64> for Number {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+/// Numbers compare by value whatever their representation: `1` equals `1.0`.
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => Some(a.cmp(b)),
+            (Self::Float(_), _) | (_, Self::Float(_)) => self.to_f64().partial_cmp(&other.to_f64()),
+            _ => Some(self.to_big().unwrap().cmp(&other.to_big().unwrap())),
+        }
+    }
+}
+
+/// Floats always show a fractional part or an exponent, so they read back
+/// as floats.
+impl Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Int(value) => write!(f, "{}", value),
+            Self::Big(value) => write!(f, "{}", value),
+            Self::Float(value) if value.is_finite() && value.fract() == 0.0 => {
+                if value.abs() < 1e16 {
+                    write!(f, "{:.1}", value)
+                } else {
+                    write!(f, "{:e}", value)
+                }
+            }
+            Self::Float(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literals() {
+        let parsed: Vec<_> = [
+            "42",
+            "-7",
+            "1_000",
+            "0xff",
+            "0b1010",
+            "-0o17",
+            "2.5",
+            "1e3",
+            "-1.5E-2",
+            "99999999999999999999",
+        ]
+        .into_iter()
+        .map(|text| Number::parse(text).unwrap().to_string())
+        .collect();
+
+        assert_eq!(
+            parsed,
+            [
+                "42",
+                "-7",
+                "1000",
+                "255",
+                "10",
+                "-15",
+                "2.5",
+                "1000.0",
+                "-0.015",
+                "99999999999999999999",
+            ]
+        );
+
+        for text in [
+            "1.2.3", "0x", "1e", "12abc", "_1", "1__0", "1.", "0b102", "inf",
+        ] {
+            assert_eq!(Number::parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        let max = Number::Int(i64::MAX);
+        let one = Number::Int(1);
+
+        assert!(matches!(max.add(&one), Number::Big(_)));
+        assert!(matches!(max.add(&one).sub(&one), Number::Int(i64::MAX)));
+        assert_eq!(
+            Number::Int(2).pow(&Number::Int(100)).to_string(),
+            "1267650600228229401496703205376"
+        );
+        assert_eq!(Number::Int(7).div(&Number::Int(2)).to_string(), "3.5");
+        assert_eq!(Number::Int(-7).quot(&Number::Int(2)), Ok(Number::Int(-3)));
+        assert_eq!(Number::Int(-7).rem(&Number::Int(2)), Ok(Number::Int(-1)));
+        assert_eq!(Number::Int(-7).modulo(&Number::Int(2)), Ok(Number::Int(1)));
+        assert_eq!(Number::Int(7).modulo(&Number::Int(-2)), Ok(Number::Int(-1)));
+        assert!(Number::Int(1).quot(&Number::Int(0)).is_err());
+        assert_eq!(Number::Int(1), Number::Float(1.0));
+        assert_eq!(Number::Float(2.0).to_string(), "2.0");
+    }
+}
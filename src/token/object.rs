@@ -0,0 +1,80 @@
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt::Display,
+    ops::{Deref, DerefMut},
+};
+
+use crate::{parser::is_identifier, source::Span, utils::escape};
+
+use super::Token;
+
+/// Values of an object literal remember where they were written until the
+/// object is changed.
+#[derive(Clone, Debug, Default)]
+pub struct Object(pub BTreeMap<String, Token>, BTreeMap<String, Span>);
+
+impl Object {
+    pub fn from_iterable<T>(iterable: T) -> Self
+    where
+        T: IntoIterator<Item = (String, Token)>,
+    {
+        Object(iterable.into_iter().collect(), BTreeMap::new())
+    }
+
+    /// Adds the value of an object literal written at `span`.
+    pub fn insert_spanned(&mut self, key: String, value: Token, span: Span) {
+        self.1.insert(key.clone(), span);
+        self.0.insert(key, value);
+    }
+
+    /// Where the value of `key` was written, if the object comes from source.
+    pub fn span(&self, key: &str) -> Option<&Span> {
+        self.1.get(key)
+    }
+}
+
+impl Deref for Object {
+    type Target = BTreeMap<String, Token>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Changing the entries forgets their locations.
+impl DerefMut for Object {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.1.clear();
+        &mut self.0
+    }
+}
+
+/// Locations don't take part in comparisons.
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl PartialOrd for Object {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+/// Keys are quoted unless they read back as identifiers, values are written
+/// with [`Token::repr`].
+impl Display for Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let entries: Vec<String> = self
+            .iter()
+            .map(|(key, value)| match is_identifier(key) {
+                true => format!("{} {}", key, value.repr()),
+                false => format!("\"{}\" {}", escape(key), value.repr()),
+            })
+            .collect();
+
+        write!(f, "{{{}}}", entries.join(" "))
+    }
+}
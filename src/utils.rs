@@ -19,3 +19,22 @@ pub fn unescape(s: &str) -> String {
 
     result
 }
+
+/// Inverse of [`unescape`], also escaping quotes so the result can be put
+/// between quotes.
+pub fn escape(s: &str) -> String {
+    let mut result = String::new();
+
+    for c in s.chars() {
+        match c {
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            c => result.push(c),
+        }
+    }
+
+    result
+}
@@ -0,0 +1,127 @@
+use crate::{host::Builtin, source::Span, token::Expression, token::Token};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    /// Push `constants[i]`
+    Const(usize),
+    Nil,
+    Pop,
+    /// Push a copy of the top of the stack
+    Dup,
+
+    /// Push a local slot, falling back to `names` lookup while the slot is
+    /// still unset
+    LoadLocal(usize),
+    /// Store the top of the stack into a local slot, keeping it on the stack
+    StoreLocal(usize),
+    /// Push the value of `names[i]` from the scope of the frame
+    LoadName(usize),
+    StoreName(usize),
+    /// Rebind the name of the target to the top of the stack, keeping it on
+    /// the stack. A slot that is still unset rebinds its name in the scope.
+    Assign(Target),
+
+    Jump(usize),
+    /// Pop the condition and jump when it's falsy
+    JumpIfFalse(usize),
+    /// Pop the condition and jump when it's truthy
+    JumpIfTrue(usize),
+
+    /// Call `builtins[i]` with `argc` values from the stack, unless a
+    /// function of its name was defined since
+    Builtin(usize, usize),
+    /// Call the function value lying below its `argc` arguments
+    Call {
+        argc: usize,
+        tail: bool,
+    },
+    /// Push the function `names[name]` calls, looking at the `local` slot
+    /// first and falling back to the builtin of that name
+    Callee {
+        name: usize,
+        local: Option<usize>,
+    },
+    Return,
+
+    /// Enter `loops[i]`, remembering the stack height to unwind to
+    EnterLoop(usize),
+    /// Leave the innermost loop, dropping its state down to its result
+    ExitLoop,
+    /// Pop a value and break out of the innermost loop with it
+    Break,
+    /// Pop a value, make it the result of the innermost loop and start its
+    /// next iteration
+    Continue,
+
+    /// Turn the collection on top of the stack into `[items index]`
+    IterList,
+    /// Fail unless the top of the stack is a number
+    ExpectNumber,
+    /// Check `[current end step]` on top of the stack
+    IterRange,
+    /// Bind the next item of `[items index]` to a slot or jump when exhausted
+    NextItem(usize, usize),
+    /// Bind the next number of `[current end step]` to a slot or jump when
+    /// exhausted
+    NextNumber(usize, usize),
+
+    /// Build an object from the keys in `constants[i]` and as many values
+    MakeObject(usize),
+
+    /// Run `expressions[i]` with the tree-walking interpreter
+    Eval(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Target {
+    Local(usize),
+    Name(usize),
+}
+
+/// The result of a loop lies on the stack below the `state` values it keeps
+/// while running, like the items and the index of a `for`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Loop {
+    pub break_target: usize,
+    pub continue_target: usize,
+    pub state: usize,
+}
+
+/// Compiled code of a function body or of a top-level expression.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub ops: Vec<Op>,
+    /// Location of the expression each op was compiled from
+    pub spans: Vec<Span>,
+    pub constants: Vec<Token>,
+    pub names: Vec<String>,
+    /// Names of the local slots, arguments come first
+    pub locals: Vec<String>,
+    pub loops: Vec<Loop>,
+    pub expressions: Vec<Expression>,
+    /// Builtins resolved at compile time, with their names
+    pub builtins: Vec<(String, Builtin)>,
+}
+
+impl Chunk {
+    pub fn emit(&mut self, op: Op, span: &Span) -> usize {
+        self.ops.push(op);
+        self.spans.push(span.clone());
+        self.ops.len() - 1
+    }
+
+    pub fn constant(&mut self, token: Token) -> usize {
+        self.constants.push(token);
+        self.constants.len() - 1
+    }
+
+    pub fn name(&mut self, name: &str) -> usize {
+        match self.names.iter().position(|n| n == name) {
+            Some(i) => i,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            }
+        }
+    }
+}
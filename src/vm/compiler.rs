@@ -0,0 +1,610 @@
+use crate::{
+    builtins::{SPECIAL_FORMS, is_else},
+    scope::{Function, Scope},
+    source::Span,
+    token::{Expression, List, Number, Token},
+};
+
+use super::chunk::{Chunk, Loop, Op, Target};
+
+/// Forms that define names in the frame they run in, so a function body
+/// using them can't be handed to the tree-walking interpreter piecemeal.
+const DEFINING_FORMS: [&str; 3] = ["func", "defmacro", "import"];
+
+/// Forms that bind names, which inside a `for` body belong to the frame of
+/// the iteration.
+const BINDING_FORMS: [&str; 4] = ["var", "func", "defmacro", "import"];
+
+/// Builtins that run code in the frame of their caller, which can't see the
+/// local slots of a compiled function.
+const SCOPE_BUILTINS: [&str; 2] = ["eval", "load"];
+
+/// The function body can't be compiled, it has to run in the tree-walking
+/// interpreter as a whole.
+#[derive(Debug)]
+pub struct Unsupported;
+
+pub struct Compiler {
+    chunk: Chunk,
+    /// The local slots in scope, innermost last
+    visible: Vec<usize>,
+    /// Where builtin calls are resolved
+    scope: Scope,
+    /// `false` for top-level code, where every name lives in the scope
+    in_function: bool,
+}
+
+impl Compiler {
+    /// Compiles a top-level expression. Anything the compiler doesn't know is
+    /// delegated to the tree-walking interpreter, so this never fails.
+    pub fn top_level(expression: &Expression, scope: &Scope) -> Chunk {
+        let mut compiler = Self {
+            chunk: Chunk::default(),
+            visible: Vec::new(),
+            scope: scope.clone(),
+            in_function: false,
+        };
+
+        // Macros defined here are only known once it runs
+        let result = match mentions(
+            &Token::Expression(expression.clone()),
+            &["defmacro".to_string()],
+        ) {
+            true => compiler.delegate(expression),
+            false => compiler.expression(expression, false),
+        };
+
+        // Loop variables may be needed by code the compiler doesn't know
+        if result.is_err() {
+            compiler.chunk = Chunk::default();
+            compiler.visible.clear();
+            compiler
+                .delegate(expression)
+                .expect("top-level code without loops always delegates");
+        }
+
+        compiler.chunk.emit(Op::Return, &expression.span);
+
+        compiler.chunk
+    }
+
+    /// Compiles a function body with its arguments, `var` bindings and `for`
+    /// variables resolved to local slots.
+    pub fn function(function: &Function) -> Result<Chunk, Unsupported> {
+        let body = function.body().ok_or(Unsupported)?;
+        let mut chunk = Chunk {
+            locals: function.params().cloned().collect(),
+            ..Default::default()
+        };
+
+        collect_locals(&Token::Expression((**body).clone()), &mut chunk.locals);
+
+        let mut compiler = Self {
+            visible: (0..chunk.locals.len()).collect(),
+            chunk,
+            scope: function.scope(),
+            in_function: true,
+        };
+
+        compiler.expression(body, true)?;
+        compiler.chunk.emit(Op::Return, &body.span);
+
+        Ok(compiler.chunk)
+    }
+
+    fn local(&self, name: &str) -> Option<usize> {
+        self.visible
+            .iter()
+            .rev()
+            .copied()
+            .find(|&slot| self.chunk.locals[slot] == name)
+    }
+
+    fn visible_names(&self) -> Vec<String> {
+        self.visible
+            .iter()
+            .map(|&slot| self.chunk.locals[slot].clone())
+            .collect()
+    }
+
+    fn target(&mut self, name: &str) -> Target {
+        match self.local(name) {
+            Some(slot) => Target::Local(slot),
+            None => Target::Name(self.chunk.name(name)),
+        }
+    }
+
+    fn patch(&mut self, at: usize, target: usize) {
+        match &mut self.chunk.ops[at] {
+            Op::Jump(to) | Op::JumpIfFalse(to) | Op::JumpIfTrue(to) => *to = target,
+            Op::NextItem(_, to) | Op::NextNumber(_, to) => *to = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn here(&self) -> usize {
+        self.chunk.ops.len()
+    }
+
+    fn token(&mut self, token: &Token, span: &Span, tail: bool) -> Result<(), Unsupported> {
+        match token {
+            Token::Nil => {
+                self.chunk.emit(Op::Nil, span);
+            }
+            Token::Identifier(id) => {
+                let op = match self.local(id) {
+                    Some(slot) => Op::LoadLocal(slot),
+                    None => Op::LoadName(self.chunk.name(id)),
+                };
+
+                self.chunk.emit(op, span);
+            }
+            Token::Expression(e) => self.expression(e, tail)?,
+            Token::Object(object) => {
+                for value in object.values() {
+                    self.token(value, span, false)?;
+                }
+
+                let keys = List::from_iterable(object.keys().cloned().map(Token::String));
+                let keys = self.chunk.constant(Token::List(keys));
+                self.chunk.emit(Op::MakeObject(keys), span);
+            }
+            _ => {
+                let i = self.chunk.constant(token.clone());
+                self.chunk.emit(Op::Const(i), span);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sequence<'a, T>(&mut self, tokens: T, span: &Span, tail: bool) -> Result<(), Unsupported>
+    where
+        T: IntoIterator<Item = &'a Token>,
+    {
+        let tokens: Vec<_> = tokens.into_iter().collect();
+
+        let Some((last, rest)) = tokens.split_last() else {
+            self.chunk.emit(Op::Nil, span);
+            return Ok(());
+        };
+
+        for token in rest {
+            self.token(token, span, false)?;
+            self.chunk.emit(Op::Pop, span);
+        }
+
+        self.token(last, span, tail)
+    }
+
+    fn expression(&mut self, e: &Expression, tail: bool) -> Result<(), Unsupported> {
+        let span = &e.span;
+
+        let Some(fid) = &e.fid else {
+            self.chunk.emit(Op::Nil, span);
+            return Ok(());
+        };
+
+        let args = &e.args;
+
+        match (fid.as_str(), args.len()) {
+            ("var", 2) => {
+                let Token::Identifier(id) = &args[0] else {
+                    return self.delegate(e);
+                };
+
+                self.token(&args[1], span, false)?;
+
+                let op = match self.target(id) {
+                    Target::Local(slot) => Op::StoreLocal(slot),
+                    Target::Name(name) => Op::StoreName(name),
+                };
+
+                self.chunk.emit(op, span);
+            }
+
+            ("set!", 2) => {
+                let Token::Identifier(id) = &args[0] else {
+                    return self.delegate(e);
+                };
+
+                self.token(&args[1], span, false)?;
+
+                let target = self.target(id);
+                self.chunk.emit(Op::Assign(target), span);
+            }
+
+            ("if", 2 | 3) => {
+                self.token(&args[0], span, false)?;
+                let to_else = self.chunk.emit(Op::JumpIfFalse(0), span);
+
+                self.token(&args[1], span, tail)?;
+                let to_end = self.chunk.emit(Op::Jump(0), span);
+
+                self.patch(to_else, self.here());
+                match args.get(2) {
+                    Some(else_block) => self.token(else_block, span, tail)?,
+                    None => {
+                        self.chunk.emit(Op::Nil, span);
+                    }
+                }
+
+                self.patch(to_end, self.here());
+            }
+
+            ("do" | "begin", _) => self.sequence(args.iter(), span, tail)?,
+
+            ("quote", 1) => {
+                let i = self.chunk.constant(args[0].clone());
+                self.chunk.emit(Op::Const(i), span);
+            }
+
+            ("and" | "or", 0) => {
+                let i = self.chunk.constant(Token::Bool(fid.as_str() == "and"));
+                self.chunk.emit(Op::Const(i), span);
+            }
+
+            // The deciding value stays on the stack when jumping to the end
+            ("and" | "or", n) => {
+                let mut to_end = Vec::new();
+
+                for token in args.iter().take(n - 1) {
+                    self.token(token, span, false)?;
+                    self.chunk.emit(Op::Dup, span);
+
+                    let jump = match fid.as_str() {
+                        "and" => Op::JumpIfFalse(0),
+                        _ => Op::JumpIfTrue(0),
+                    };
+
+                    to_end.push(self.chunk.emit(jump, span));
+                    self.chunk.emit(Op::Pop, span);
+                }
+
+                self.token(&args[n - 1], span, tail)?;
+
+                for jump in to_end {
+                    self.patch(jump, self.here());
+                }
+            }
+
+            ("cond", _) => {
+                let clauses: Option<Vec<&List>> = args
+                    .iter()
+                    .map(|clause| match clause {
+                        Token::List(clause) if !clause.is_empty() => Some(clause),
+                        _ => None,
+                    })
+                    .collect();
+
+                let Some(clauses) = clauses else {
+                    return self.delegate(e);
+                };
+
+                let mut to_end = Vec::new();
+
+                for clause in clauses {
+                    let to_next = match is_else(&clause[0]) {
+                        true => None,
+                        false => {
+                            self.token(&clause[0], span, false)?;
+                            Some(self.chunk.emit(Op::JumpIfFalse(0), span))
+                        }
+                    };
+
+                    self.sequence(clause.iter().skip(1), span, tail)?;
+                    to_end.push(self.chunk.emit(Op::Jump(0), span));
+
+                    if let Some(jump) = to_next {
+                        self.patch(jump, self.here());
+                    }
+                }
+
+                self.chunk.emit(Op::Nil, span);
+
+                for jump in to_end {
+                    self.patch(jump, self.here());
+                }
+            }
+
+            ("call", 1..) => {
+                for token in args.iter() {
+                    self.token(token, span, false)?;
+                }
+
+                self.chunk.emit(
+                    Op::Call {
+                        argc: args.len() - 1,
+                        tail,
+                    },
+                    span,
+                );
+            }
+
+            ("while", 1..) => {
+                // Names bound in the body live in the frame of each iteration
+                if args.iter().skip(1).any(binds) {
+                    return self.delegate(e);
+                }
+
+                self.chunk.emit(Op::Nil, span);
+                let i = self.enter_loop(0, span);
+                let start = self.here();
+
+                self.token(&args[0], span, false)?;
+                let to_end = self.chunk.emit(Op::JumpIfFalse(0), span);
+
+                self.loop_body(args.iter().skip(1), span)?;
+                self.chunk.emit(Op::Jump(start), span);
+
+                self.patch(to_end, self.here());
+                self.exit_loop(i, start, span);
+            }
+
+            ("loop", _) => {
+                if args.iter().any(binds) {
+                    return self.delegate(e);
+                }
+
+                self.chunk.emit(Op::Nil, span);
+                let i = self.enter_loop(0, span);
+                let start = self.here();
+
+                self.loop_body(args.iter(), span)?;
+                self.chunk.emit(Op::Jump(start), span);
+
+                self.exit_loop(i, start, span);
+            }
+
+            ("for", 1..) => {
+                let Token::List(binding) = &args[0] else {
+                    return self.delegate(e);
+                };
+
+                let (Some(Token::Identifier(id)), 2..=4) = (binding.front(), binding.len()) else {
+                    return self.delegate(e);
+                };
+
+                // Names bound in the body live in the frame of each iteration
+                if args.iter().skip(1).any(binds) {
+                    return self.delegate(e);
+                }
+
+                // Bad values are reported where they are written in the binding
+                let at = |i| binding.span(i).unwrap_or(span);
+
+                self.chunk.emit(Op::Nil, span);
+
+                let (state, next): (_, fn(usize, usize) -> Op) = if binding.len() == 2 {
+                    self.token(&binding[1], span, false)?;
+                    self.chunk.emit(Op::IterList, at(1));
+                    (2, Op::NextItem)
+                } else {
+                    for (i, token) in binding.iter().enumerate().skip(1) {
+                        self.token(token, span, false)?;
+                        self.chunk.emit(Op::ExpectNumber, at(i));
+                    }
+
+                    if binding.len() == 3 {
+                        let one = self.chunk.constant(Token::Number(Number::Int(1)));
+                        self.chunk.emit(Op::Const(one), span);
+                    }
+
+                    self.chunk.emit(Op::IterRange, span);
+                    (3, Op::NextNumber)
+                };
+
+                // The loop variable gets a slot of its own, shadowing any
+                // outer binding of the name until the loop ends
+                self.chunk.locals.push(id.0.clone());
+                let slot = self.chunk.locals.len() - 1;
+
+                let i = self.enter_loop(state, span);
+                let start = self.here();
+
+                let to_end = self.chunk.emit(next(slot, 0), span);
+                self.visible.push(slot);
+                self.loop_body(args.iter().skip(1), span)?;
+                self.visible.pop();
+                self.chunk.emit(Op::Jump(start), span);
+
+                self.patch(to_end, self.here());
+                self.exit_loop(i, start, span);
+            }
+
+            ("break", 0 | 1) => {
+                match args.front() {
+                    Some(value) => self.token(value, span, false)?,
+                    None => {
+                        self.chunk.emit(Op::Nil, span);
+                    }
+                }
+
+                self.chunk.emit(Op::Break, span);
+            }
+
+            ("continue", 0 | 1) => {
+                match args.front() {
+                    Some(value) => self.token(value, span, false)?,
+                    None => {
+                        self.chunk.emit(Op::Nil, span);
+                    }
+                }
+
+                self.chunk.emit(Op::Continue, span);
+            }
+
+            (name, _) if SPECIAL_FORMS.contains_key(name) => {
+                return self.delegate(e);
+            }
+
+            // Expansions may bind names the local slots don't know about
+            (name, _) if self.scope.get_macro(name).is_some() => {
+                if self.in_frame() {
+                    return Err(Unsupported);
+                }
+
+                return self.delegate(e);
+            }
+
+            (name, _) if self.in_frame() && SCOPE_BUILTINS.contains(&name) => {
+                return Err(Unsupported);
+            }
+
+            (name, argc) => {
+                // A local slot may hold a function shadowing the builtin
+                if let (None, Some(builtin)) = (self.local(name), self.scope.get_builtin(name)) {
+                    for token in args.iter() {
+                        self.token(token, span, false)?;
+                    }
+
+                    self.chunk.builtins.push((name.to_string(), builtin));
+                    self.chunk
+                        .emit(Op::Builtin(self.chunk.builtins.len() - 1, argc), span);
+
+                    return Ok(());
+                }
+
+                // Like the tree-walker, an unknown callee fails before its
+                // arguments run
+                let callee = Op::Callee {
+                    name: self.chunk.name(name),
+                    local: self.local(name),
+                };
+                self.chunk.emit(callee, span);
+
+                for token in args.iter() {
+                    self.token(token, span, false)?;
+                }
+
+                self.chunk.emit(Op::Call { argc, tail }, span);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the code runs with local slots the scope can't see.
+    fn in_frame(&self) -> bool {
+        self.in_function || !self.visible.is_empty()
+    }
+
+    /// Starts a loop keeping `state` values on the stack above its result,
+    /// which the caller has pushed first.
+    fn enter_loop(&mut self, state: usize, span: &Span) -> usize {
+        self.chunk.loops.push(Loop {
+            state,
+            ..Loop::default()
+        });
+        let i = self.chunk.loops.len() - 1;
+
+        self.chunk.emit(Op::EnterLoop(i), span);
+        i
+    }
+
+    /// Finishes a loop whose body starts at `start`: a normal exit yields the
+    /// value of the last `continue`, `break` jumps straight to `ExitLoop`
+    /// with its value in place of the result.
+    fn exit_loop(&mut self, i: usize, start: usize, span: &Span) {
+        let exit = self.chunk.emit(Op::ExitLoop, span);
+
+        self.chunk.loops[i].break_target = exit;
+        self.chunk.loops[i].continue_target = start;
+    }
+
+    fn loop_body<'a, T>(&mut self, body: T, span: &Span) -> Result<(), Unsupported>
+    where
+        T: IntoIterator<Item = &'a Token>,
+    {
+        for token in body {
+            self.token(token, span, false)?;
+            self.chunk.emit(Op::Pop, span);
+        }
+
+        Ok(())
+    }
+
+    /// Leaves `e` to the tree-walking interpreter. That is only possible when
+    /// `e` doesn't read local slots, nor defines names inside a function body.
+    fn delegate(&mut self, e: &Expression) -> Result<(), Unsupported> {
+        let defines = e
+            .fid
+            .as_ref()
+            .is_some_and(|fid| DEFINING_FORMS.contains(&fid.as_str()));
+
+        if (self.in_function && defines)
+            || mentions(&Token::Expression(e.clone()), &self.visible_names())
+        {
+            return Err(Unsupported);
+        }
+
+        self.chunk.expressions.push(e.clone());
+        let i = self.chunk.expressions.len() - 1;
+        self.chunk.emit(Op::Eval(i), &e.span);
+
+        Ok(())
+    }
+}
+
+/// Adds the names bound by `var` in a function body, skipping the bodies of
+/// nested functions and loops which get frames of their own.
+fn collect_locals(token: &Token, locals: &mut Vec<String>) {
+    match token {
+        Token::Expression(e) => {
+            let fid = e.fid.as_ref().map(|fid| fid.as_str());
+
+            if matches!(fid, Some("lambda" | "fn" | "func" | "for")) {
+                return;
+            }
+
+            if let (Some("var"), Some(Token::Identifier(id))) = (fid, e.args.front())
+                && !locals.contains(&id.0)
+            {
+                locals.push(id.0.clone());
+            }
+
+            for arg in e.args.iter() {
+                collect_locals(arg, locals);
+            }
+        }
+        Token::Object(object) => {
+            for value in object.values() {
+                collect_locals(value, locals);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Whether `token` binds a name in the frame it runs in, outside the bodies of
+/// nested functions.
+fn binds(token: &Token) -> bool {
+    match token {
+        Token::Expression(e) => {
+            let fid = e.fid.as_ref().map(|fid| fid.as_str());
+
+            match fid {
+                Some("lambda" | "fn") => false,
+                Some(fid) if BINDING_FORMS.contains(&fid) => true,
+                _ => e.args.iter().any(binds),
+            }
+        }
+        Token::List(list) => list.iter().any(binds),
+        Token::Object(object) => object.values().any(binds),
+        _ => false,
+    }
+}
+
+/// Whether any of `names` appears anywhere inside `token`.
+fn mentions(token: &Token, names: &[String]) -> bool {
+    match token {
+        Token::Identifier(id) => names.contains(&id.0),
+        Token::Expression(e) => {
+            e.fid.as_ref().is_some_and(|fid| names.contains(&fid.0))
+                || e.args.iter().any(|arg| mentions(arg, names))
+        }
+        Token::List(list) => list.iter().any(|item| mentions(item, names)),
+        Token::Object(object) => object.values().any(|value| mentions(value, names)),
+        _ => false,
+    }
+}
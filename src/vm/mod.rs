@@ -0,0 +1,758 @@
+use std::{
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    errors::{CallFrame, RuntimeError},
+    scope::{Function, Scope},
+    token::{Expression, List, Number, Object, Token},
+};
+
+use chunk::{Chunk, Op, Target};
+use compiler::Compiler;
+
+mod chunk;
+mod compiler;
+
+/// Size of the function cache below which dropped bodies aren't pruned
+const MIN_PRUNE: usize = 64;
+
+struct Frame {
+    chunk: Rc<Chunk>,
+    pc: usize,
+    locals: Vec<Option<Token>>,
+    /// Scope the function was defined in, or the global scope
+    scope: Scope,
+    /// Stack height when the frame was entered
+    base: usize,
+    /// Active loops as `(loop index, stack height)`, innermost last
+    loops: Vec<(usize, usize)>,
+    /// `None` for top-level code
+    call: Option<CallFrame>,
+}
+
+/// Stack machine running compiled [`Chunk`]s. Function bodies are compiled on
+/// their first call and cached; bodies the compiler can't handle run in the
+/// tree-walking interpreter, so both engines share the same values.
+#[derive(Default)]
+pub struct Vm {
+    stack: Vec<Token>,
+    frames: Vec<Frame>,
+    /// Keyed by the address of the function body. The weak reference keeps
+    /// the address from being reused while the entry exists.
+    functions: HashMap<*const Expression, (Weak<Expression>, Option<Rc<Chunk>>)>,
+    /// Cache size at which entries of dropped bodies are pruned next
+    prune_at: usize,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn execute(
+        &mut self,
+        expression: &Expression,
+        scope: &Scope,
+    ) -> Result<Token, RuntimeError> {
+        let chunk = Rc::new(Compiler::top_level(expression, scope));
+
+        self.frames.push(Frame {
+            locals: vec![None; chunk.locals.len()],
+            chunk,
+            pc: 0,
+            scope: scope.clone(),
+            base: self.stack.len(),
+            loops: Vec::new(),
+            call: None,
+        });
+
+        self.run()
+    }
+
+    /// Drops compiled function bodies, they hold on to the builtins that
+    /// were defined when they were compiled.
+    pub fn clear_cache(&mut self) {
+        self.functions.clear();
+    }
+
+    fn compiled(&mut self, function: &Function) -> Option<Rc<Chunk>> {
+        let body = function.body()?;
+
+        if let Some((_, chunk)) = self.functions.get(&Rc::as_ptr(body)) {
+            return chunk.clone();
+        }
+
+        // Every evaluated `fn` has a body of its own, closures made in a loop
+        // would pile up otherwise
+        if self.functions.len() >= self.prune_at {
+            self.functions
+                .retain(|_, (body, _)| body.strong_count() > 0);
+            self.prune_at = (self.functions.len() * 2).max(MIN_PRUNE);
+        }
+
+        let chunk = Compiler::function(function).ok().map(Rc::new);
+        self.functions
+            .insert(Rc::as_ptr(body), (Rc::downgrade(body), chunk.clone()));
+
+        chunk
+    }
+
+    /// Runs until the frame on top when called returns.
+    fn run(&mut self) -> Result<Token, RuntimeError> {
+        let entry = self.frames.len();
+
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let pc = frame.pc;
+            frame.pc += 1;
+
+            let op = frame.chunk.ops[pc];
+
+            match self.dispatch(op) {
+                Ok(None) => (),
+                Ok(Some(value)) => {
+                    if self.frames.len() < entry {
+                        return Ok(value);
+                    }
+
+                    self.stack.push(value);
+                }
+                Err(error) => {
+                    let span = self.frames.last().unwrap().chunk.spans[pc].clone();
+                    self.unwind(error.located(span), entry)?;
+                }
+            }
+        }
+    }
+
+    /// Hands `break`/`continue` to the innermost active loop, otherwise pops
+    /// frames until the error leaves the frame `run` started with.
+    fn unwind(&mut self, mut error: RuntimeError, entry: usize) -> Result<(), RuntimeError> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+
+            if let Some(&(i, height)) = frame.loops.last() {
+                let target = frame.chunk.loops[i];
+
+                let result = height - target.state - 1;
+
+                match error {
+                    RuntimeError::Break(value) => {
+                        frame.pc = target.break_target;
+                        self.stack.truncate(height);
+                        self.stack[result] = *value;
+                        return Ok(());
+                    }
+                    RuntimeError::Continue(value) => {
+                        frame.pc = target.continue_target;
+                        self.stack.truncate(height);
+                        self.stack[result] = *value;
+                        return Ok(());
+                    }
+                    _ => (),
+                }
+            }
+
+            let frame = self.frames.pop().unwrap();
+            self.stack.truncate(frame.base);
+
+            if let Some(call) = frame.call {
+                error = error.unwound(call);
+            }
+
+            if self.frames.len() < entry {
+                return Err(error);
+            }
+        }
+    }
+
+    /// Executes one op. Returns the value of a frame that just returned.
+    fn dispatch(&mut self, op: Op) -> Result<Option<Token>, RuntimeError> {
+        let frame = self.frames.last_mut().unwrap();
+
+        match op {
+            Op::Const(i) => self.stack.push(frame.chunk.constants[i].clone()),
+            Op::Nil => self.stack.push(Token::Nil),
+            Op::Pop => {
+                self.stack.pop();
+            }
+            Op::Dup => self.stack.push(self.stack.last().unwrap().clone()),
+
+            Op::LoadLocal(slot) => {
+                let value = match &frame.locals[slot] {
+                    Some(value) => value.clone(),
+                    None => frame.scope.get_variable(&frame.chunk.locals[slot]),
+                };
+
+                self.stack.push(value);
+            }
+            Op::StoreLocal(slot) => frame.locals[slot] = self.stack.last().cloned(),
+            Op::LoadName(name) => {
+                let value = frame.scope.get_variable(&frame.chunk.names[name]);
+                self.stack.push(value);
+            }
+            Op::StoreName(name) => {
+                let value = self.stack.last().unwrap().clone();
+                frame.scope.set_variable(&frame.chunk.names[name], value);
+            }
+            Op::Assign(target) => {
+                let value = self.stack.last().unwrap().clone();
+
+                match target {
+                    Target::Local(slot) if frame.locals[slot].is_some() => {
+                        frame.locals[slot] = Some(value);
+                    }
+                    Target::Local(slot) => frame.scope.assign(&frame.chunk.locals[slot], value)?,
+                    Target::Name(name) => frame.scope.assign(&frame.chunk.names[name], value)?,
+                }
+            }
+
+            Op::Jump(to) => frame.pc = to,
+            Op::JumpIfFalse(to) => {
+                let condition: bool = self.stack.pop().unwrap().extract(&frame.scope)?;
+
+                if !condition {
+                    frame.pc = to;
+                }
+            }
+            Op::JumpIfTrue(to) => {
+                let condition: bool = self.stack.pop().unwrap().extract(&frame.scope)?;
+
+                if condition {
+                    frame.pc = to;
+                }
+            }
+
+            Op::Builtin(i, argc) => {
+                let args = pop_n(&mut self.stack, argc);
+                let (name, builtin) = &frame.chunk.builtins[i];
+
+                if let Some(function) = frame.scope.get_function(name) {
+                    return self.call(function, args, false);
+                }
+
+                let value = builtin.call(List::from_iterable(args), &frame.scope)?;
+                self.stack.push(value);
+            }
+            Op::Call { argc, tail } => {
+                let args = pop_n(&mut self.stack, argc);
+                let function: Function = self.stack.pop().unwrap().extract(&frame.scope)?;
+
+                return self.call(function, args, tail);
+            }
+            Op::Callee { name, local } => {
+                let local = local.and_then(|slot| match &frame.locals[slot] {
+                    Some(Token::Function(f)) => Some(f.clone()),
+                    _ => None,
+                });
+
+                let name = &frame.chunk.names[name];
+
+                // A slot holding something else doesn't hide the builtin
+                let function = local
+                    .or_else(|| frame.scope.get_function(name))
+                    .or_else(|| {
+                        let builtin = frame.scope.get_builtin(name)?;
+                        Some(Function::native(name, builtin, frame.scope.clone()))
+                    })
+                    .ok_or_else(|| RuntimeError::UndefinedFunction(name.clone()))?;
+
+                self.stack.push(Token::Function(function));
+            }
+            Op::Return => {
+                let value = self.stack.pop().unwrap();
+                let frame = self.frames.pop().unwrap();
+                self.stack.truncate(frame.base);
+
+                return Ok(Some(value));
+            }
+
+            Op::EnterLoop(i) => frame.loops.push((i, self.stack.len())),
+            Op::ExitLoop => {
+                let (i, height) = frame.loops.pop().unwrap();
+                self.stack.truncate(height - frame.chunk.loops[i].state);
+            }
+            Op::Break => {
+                let value = self.stack.pop().unwrap();
+                return Err(RuntimeError::Break(Box::new(value)));
+            }
+            Op::Continue => {
+                let value = self.stack.pop().unwrap();
+                return Err(RuntimeError::Continue(Box::new(value)));
+            }
+
+            Op::IterList => {
+                let items = match self.stack.pop().unwrap() {
+                    Token::List(list) => list,
+                    Token::String(string) => {
+                        List::from_iterable(string.chars().map(|c| Token::String(c.into())))
+                    }
+                    token => {
+                        return Err(RuntimeError::TypeMismatch {
+                            expected: "list".to_string(),
+                            found: token.as_type(),
+                        });
+                    }
+                };
+
+                self.stack.push(Token::List(items));
+                self.stack.push(Token::Number(Number::Int(0)));
+            }
+            Op::ExpectNumber => {
+                let token = self.stack.last().unwrap();
+
+                if !matches!(token, Token::Number(_)) {
+                    return Err(RuntimeError::TypeMismatch {
+                        expected: "number".to_string(),
+                        found: token.as_type(),
+                    });
+                }
+            }
+            Op::IterRange => {
+                let mut values = Vec::with_capacity(3);
+
+                for token in pop_n(&mut self.stack, 3) {
+                    values.push(token.extract::<Number>(&frame.scope)?);
+                }
+
+                if values[2].is_zero() {
+                    return Err(RuntimeError::InvalidValue(
+                        "for step can't be zero".to_string(),
+                    ));
+                }
+
+                self.stack.extend(values.into_iter().map(Token::Number));
+            }
+            Op::NextItem(slot, exit) => {
+                let len = self.stack.len();
+
+                let Token::Number(Number::Int(index)) = self.stack[len - 1] else {
+                    unreachable!()
+                };
+
+                let Token::List(items) = &self.stack[len - 2] else {
+                    unreachable!()
+                };
+
+                match items.get(index as usize).cloned() {
+                    Some(item) => {
+                        self.stack[len - 1] = Token::Number(Number::Int(index + 1));
+                        frame.locals[slot] = Some(item);
+                    }
+                    None => frame.pc = exit,
+                }
+            }
+            Op::NextNumber(slot, exit) => {
+                let len = self.stack.len();
+
+                let [
+                    Token::Number(current),
+                    Token::Number(end),
+                    Token::Number(step),
+                ] = &self.stack[len - 3..]
+                else {
+                    unreachable!()
+                };
+
+                if current.in_range(end, step) {
+                    let current = current.clone();
+                    self.stack[len - 3] = Token::Number(current.add(step));
+                    frame.locals[slot] = Some(Token::Number(current));
+                } else {
+                    frame.pc = exit;
+                }
+            }
+
+            Op::MakeObject(keys) => {
+                let Token::List(keys) = frame.chunk.constants[keys].clone() else {
+                    unreachable!()
+                };
+
+                let values = pop_n(&mut self.stack, keys.len());
+                let mut object = Object::default();
+
+                for (key, value) in keys.0.into_iter().zip(values) {
+                    object.insert(key.to_string(), value);
+                }
+
+                self.stack.push(Token::Object(object));
+            }
+
+            Op::Eval(i) => {
+                let value = frame.chunk.expressions[i].execute(&frame.scope)?;
+                self.stack.push(value);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Pushes a frame for `function`, replacing the current one for tail
+    /// calls. Functions that don't compile are applied by the tree-walker.
+    fn call(
+        &mut self,
+        function: Function,
+        args: Vec<Token>,
+        tail: bool,
+    ) -> Result<Option<Token>, RuntimeError> {
+        let Some(chunk) = self.compiled(&function) else {
+            let value = function.apply(args)?;
+
+            if tail {
+                self.stack.push(value);
+                return self.dispatch(Op::Return);
+            }
+
+            return Ok(Some(value));
+        };
+
+        let args = function.bind_args(args)?;
+
+        let caller = self.frames.last().unwrap();
+        let call_site = caller.chunk.spans[caller.pc - 1].clone();
+
+        if tail {
+            let frame = self.frames.pop().unwrap();
+            self.stack.truncate(frame.base);
+        }
+
+        let call = CallFrame {
+            function: function.name().to_string(),
+            args: function
+                .params()
+                .cloned()
+                .zip(args.iter().cloned())
+                .collect(),
+            call_site: Some(call_site),
+        };
+
+        let mut locals: Vec<Option<Token>> = args.into_iter().map(Some).collect();
+        locals.resize(chunk.locals.len(), None);
+
+        self.frames.push(Frame {
+            chunk,
+            pc: 0,
+            locals,
+            scope: function.scope(),
+            base: self.stack.len(),
+            loops: Vec::new(),
+            call: Some(call),
+        });
+
+        Ok(None)
+    }
+}
+
+fn pop_n(stack: &mut Vec<Token>, n: usize) -> Vec<Token> {
+    stack.split_off(stack.len() - n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, source::line_col};
+
+    /// Runs `source` with both engines and checks they agree on the value of
+    /// the last expression.
+    fn run(source: &str) -> Result<Token, RuntimeError> {
+        let expressions = Parser::with_name("<test>", source)
+            .parse_expressions()
+            .unwrap();
+
+        let (tree, vm) = (Scope::new(), Scope::new());
+        let mut machine = Vm::new();
+        let mut results = (Ok(Token::Nil), Ok(Token::Nil));
+
+        for e in &expressions {
+            results = (e.execute(&tree), machine.execute(e, &vm));
+        }
+
+        assert_eq!(results.0, results.1);
+        results.1
+    }
+
+    #[test]
+    fn loop_variables() {
+        let source = "
+            (var total 0)
+            (for [i 0 100] (set! total (add total i)))
+            (do total)";
+
+        assert_eq!(run(source), Ok(Token::Number(Number::Int(4950))));
+        assert_eq!(
+            run("(var s \"\") (for [c \"abc\"] (set! s (concat s c))) (do s)"),
+            Ok(Token::String("abc".to_string()))
+        );
+    }
+
+    #[test]
+    fn recursion() {
+        let source = "
+            (func fib [n] (if (lt n 2) n (add (fib (sub n 1)) (fib (sub n 2)))))
+            (fib 15)";
+
+        assert_eq!(run(source), Ok(Token::Number(Number::Int(610))));
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let first_over = "
+            (func first_over [items limit]
+              (for [x items]
+                (if (le x limit) (continue))
+                (if (gt x 10) (break x))))";
+
+        assert_eq!(
+            run(&format!("{} (first_over [1 3 4 7 9 12 14] 5)", first_over)),
+            Ok(Token::Number(Number::Int(12)))
+        );
+        assert_eq!(
+            run(&format!("{} (first_over [1] 5)", first_over)),
+            Ok(Token::Nil)
+        );
+
+        let source = "
+            (func count []
+              (var n 0)
+              (loop (set! n (add n 1)) (if (eq n 7) (break (mul n 10)))))
+            (count)";
+
+        assert_eq!(run(source), Ok(Token::Number(Number::Int(70))));
+    }
+
+    #[test]
+    fn object_literals() {
+        let source = "
+            (func pair [k v] {key k value v})
+            (get (pair \"a\" 1) \"value\")";
+
+        assert_eq!(run(source), Ok(Token::Number(Number::Int(1))));
+    }
+
+    #[test]
+    fn closures() {
+        let adder = "(func adder [n] (lambda [x] (add x n)))";
+
+        assert_eq!(
+            run(&format!(
+                "{} (func twice [f x] (f (f x))) (twice (adder 3) 1)",
+                adder
+            )),
+            Ok(Token::Number(Number::Int(7)))
+        );
+        assert_eq!(
+            run(&format!("{} (call (adder 10) 5)", adder)),
+            Ok(Token::Number(Number::Int(15)))
+        );
+    }
+
+    #[test]
+    fn nested_definitions() {
+        let source = "
+            (func local_helper [x] (func twice [y] (mul y 2)) (twice x))
+            (local_helper 4)";
+
+        assert_eq!(run(source), Ok(Token::Number(Number::Int(8))));
+    }
+
+    #[test]
+    fn conditionals() {
+        let classify = "
+            (func classify [n]
+              (cond [(and (gt n 0) (lt n 10)) \"small\"]
+                    [(or (eq n 0) (ge n 100)) (case n [0 \"zero\"] [else \"huge\"])]
+                    [else \"other\"]))";
+
+        for (n, expected) in [(5, "small"), (0, "zero"), (500, "huge"), (50, "other")] {
+            assert_eq!(
+                run(&format!("{} (classify {})", classify, n)),
+                Ok(Token::String(expected.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn tail_position_of_or() {
+        let source = "
+            (func count_down [n] (or (eq n 0) (count_down (sub n 1))))
+            (count_down 20000)";
+
+        assert_eq!(run(source), Ok(Token::Bool(true)));
+    }
+
+    #[test]
+    fn locals_in_function_bodies() {
+        let source = "
+            (func sum_squares [n]
+              (var total 0)
+              (for [i 0 n] (set! total (add total (mul i i))))
+              total)
+            (sum_squares 4)";
+
+        assert_eq!(run(source), Ok(Token::Number(Number::Int(14))));
+    }
+
+    #[test]
+    fn let_forms() {
+        assert_eq!(
+            run("(func hyp [a b] (let* [a2 (mul a a) b2 (mul b b)] (add a2 b2))) (hyp 3 4)"),
+            Ok(Token::Number(Number::Int(25)))
+        );
+        assert_eq!(
+            run("(let [x 1] (var y 2) (add x y))"),
+            Ok(Token::Number(Number::Int(3)))
+        );
+        assert_eq!(run("(let [x 1] (var y 2)) (do y)"), Ok(Token::Nil));
+    }
+
+    #[test]
+    fn exceptions() {
+        let safe_div = "
+            (func safe_div [a b]
+              (try (if (eq b 0) (throw \"division by zero\") (div a b))
+                   (catch e (get e \"message\"))))";
+
+        assert_eq!(
+            run(&format!("{} (safe_div 1 2)", safe_div)),
+            Ok(Token::Number(Number::Float(0.5)))
+        );
+        assert_eq!(
+            run(&format!("{} (safe_div 1 0)", safe_div)),
+            Ok(Token::String("division by zero".to_string()))
+        );
+    }
+
+    #[test]
+    fn macros_in_function_bodies() {
+        let source = "
+            (defmacro unless [test & body] `(if ,test nil (do ,@body)))
+            (func check [x] (unless (lt x 0) (var y (mul x 2)) (add y 1)))";
+
+        assert_eq!(
+            run(&format!("{} (check 3)", source)),
+            Ok(Token::Number(Number::Int(7)))
+        );
+        assert_eq!(run(&format!("{} (check -1)", source)), Ok(Token::Nil));
+    }
+
+    #[test]
+    fn rest_arguments() {
+        let source = "
+            (func len [items] (var n 0) (for [_ items] (set! n (add n 1))) n)
+            (func count [first & rest] (add first (call len rest)))
+            (count 10 1 2 3)";
+
+        assert_eq!(run(source), Ok(Token::Number(Number::Int(13))));
+    }
+
+    #[test]
+    fn eval() {
+        assert_eq!(
+            run("(func scaled [x] (var k 10) (eval (read \"(mul x k)\"))) (scaled 4)"),
+            Ok(Token::Number(Number::Int(40)))
+        );
+        assert_eq!(
+            run("(var code '(add 1 2)) (eval code)"),
+            Ok(Token::Number(Number::Int(3)))
+        );
+        assert_eq!(
+            run("(var code '(add 1 2)) (eval 'code)").map(|code| code.to_string()),
+            Ok("(add 1 2)".to_string())
+        );
+    }
+
+    #[test]
+    fn functions_shadow_builtins() {
+        assert_eq!(
+            run("(func max [a b] \"mine\") (max 1 2)"),
+            Ok(Token::String("mine".to_string()))
+        );
+        assert_eq!(
+            run("(func f [] (max 1 2)) (f) (func max [a b] \"later\") (f)"),
+            Ok(Token::String("later".to_string()))
+        );
+    }
+
+    #[test]
+    fn arguments_shadow_builtins() {
+        let source = "
+            (func g [first] (first 1))
+            (g (lambda [x] (add x 1)))";
+
+        assert_eq!(run(source), Ok(Token::Number(Number::Int(2))));
+        assert_eq!(
+            run("(func h [first] (first [1 2])) (h [3])"),
+            Ok(Token::Number(Number::Int(1)))
+        );
+    }
+
+    #[test]
+    fn tail_calls() {
+        let source = "
+            (func even [n] (if (eq n 0) true (odd (sub n 1))))
+            (func odd [n] (if (eq n 0) false (even (sub n 1))))
+            (even 20001)";
+
+        assert_eq!(run(source), Ok(Token::Bool(false)));
+    }
+
+    #[test]
+    fn dropped_functions_leave_the_cache() {
+        let expressions = Parser::with_name(
+            "<test>",
+            "(func make [n] (lambda [] n)) (for [i 0 1000] (call (make i)))",
+        )
+        .parse_expressions()
+        .unwrap();
+
+        let scope = Scope::new();
+        let mut machine = Vm::new();
+
+        for e in &expressions {
+            machine.execute(e, &scope).unwrap();
+        }
+
+        assert!(machine.functions.len() <= MIN_PRUNE * 2);
+    }
+
+    #[test]
+    fn backtrace() {
+        let source =
+            "(func inner [x] (add x \"b\"))\n(func outer [y] (add 1 (inner y)))\n(outer 1)";
+
+        let error = run(source).unwrap_err();
+        let trace: Vec<_> = error
+            .trace()
+            .iter()
+            .map(|frame| {
+                (
+                    frame.function.as_str(),
+                    frame.call_site.as_ref().and_then(line_col),
+                )
+            })
+            .collect();
+
+        assert_eq!(trace, [("inner", Some((2, 24))), ("outer", Some((3, 1)))]);
+    }
+
+    #[test]
+    fn break_outside_a_loop() {
+        assert_eq!(
+            run("(func f [] (break 1))\n(f)").unwrap_err().kind(),
+            &RuntimeError::OutsideLoop("break".to_string())
+        );
+    }
+
+    #[test]
+    fn callee_before_arguments() {
+        // Evaluating the arguments first would recurse forever
+        assert_eq!(
+            run("(func f [n] (nosuch (f 1)))\n(f 1)")
+                .unwrap_err()
+                .kind(),
+            &RuntimeError::UndefinedFunction("nosuch".to_string())
+        );
+    }
+}